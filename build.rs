@@ -1,10 +1,22 @@
+use std::env;
+use std::path::PathBuf;
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Lets the server describe its own services to reflection clients.
+    let descriptor_path = PathBuf::from(env::var("OUT_DIR")?).join("services_descriptor.bin");
     tonic_build::configure()
         .build_server(true)
         .protoc_arg("--experimental_allow_proto3_optional")
+        .file_descriptor_set_path(&descriptor_path)
         .compile(
-            &["proto/services.proto"], // Path to your proto file
-            &["proto"],                // Directory where the proto file is located
+            &[
+                "proto/services.proto", // Path to your proto file
+                "proto/google/rpc/status.proto",
+                "proto/google/rpc/error_details.proto",
+                "proto/grpc/health/v1/health.proto",
+                "proto/grpc/reflection/v1alpha/reflection.proto",
+            ],
+            &["proto"], // Directory where the proto file is located
         )?;
     Ok(())
-}
\ No newline at end of file
+}
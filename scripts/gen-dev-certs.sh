@@ -0,0 +1,34 @@
+#!/usr/bin/env sh
+# Generates a throwaway CA plus server and client certificates for trying TLS and
+# mutual TLS on localhost. Everything is written to ./certs; never use these in production.
+set -eu
+
+out=certs
+mkdir -p "$out"
+cd "$out"
+
+# Certificate authority that signs both the server and the client certificate
+openssl req -x509 -newkey rsa:2048 -nodes -days 365 \
+    -keyout ca.key -out ca.pem -subj "/CN=grpc-tutorial dev CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign"
+
+issue() {
+    name=$1
+    subject=$2
+    extensions=$3
+
+    openssl req -newkey rsa:2048 -nodes -keyout "$name.key" -out "$name.csr" -subj "$subject"
+    printf '%s\n' "$extensions" > "$name.ext"
+    openssl x509 -req -in "$name.csr" -CA ca.pem -CAkey ca.key -CAcreateserial \
+        -days 365 -out "$name.pem" -extfile "$name.ext"
+    rm "$name.csr" "$name.ext"
+}
+
+issue server "/CN=localhost" \
+    "subjectAltName=DNS:localhost,IP:127.0.0.1,IP:::1
+extendedKeyUsage=serverAuth"
+issue client "/CN=grpc-client" \
+    "extendedKeyUsage=clientAuth"
+
+echo "Wrote CA, server and client certificates to $(pwd)"
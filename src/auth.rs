@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+/// The authenticated identity behind a request.
+#[derive(Debug, Clone)]
+pub struct Caller {
+    pub user_id: String,
+    pub roles: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenEntry {
+    token: String,
+    user_id: String,
+    #[serde(default)]
+    roles: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokensFile {
+    #[serde(default)]
+    tokens: Vec<TokenEntry>,
+}
+
+/// Interceptor that resolves the `authorization: Bearer <token>` metadata to a `Caller`
+/// and stores it in the request extensions, rejecting requests without a known token.
+#[derive(Clone)]
+pub struct Authenticator {
+    tokens: Arc<HashMap<String, Caller>>,
+}
+
+impl Authenticator {
+    /// Reads the bearer tokens the server accepts from a TOML file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to read {}: {}", path.display(), e),
+            )
+        })?;
+        let file: TokensFile =
+            toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let tokens = file
+            .tokens
+            .into_iter()
+            .map(|entry| {
+                (
+                    entry.token,
+                    Caller {
+                        user_id: entry.user_id,
+                        roles: entry.roles,
+                    },
+                )
+            })
+            .collect();
+        Ok(Authenticator {
+            tokens: Arc::new(tokens),
+        })
+    }
+}
+
+impl Interceptor for Authenticator {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let header = request
+            .metadata()
+            .get("authorization")
+            .ok_or_else(|| Status::unauthenticated("missing authorization metadata"))?;
+        let token = header
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| Status::unauthenticated("authorization must be a bearer token"))?;
+        let caller = self
+            .tokens
+            .get(token.trim())
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("unknown bearer token"))?;
+
+        request.extensions_mut().insert(caller);
+        Ok(request)
+    }
+}
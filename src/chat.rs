@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use prost::Message;
+use tokio::sync::mpsc::Sender;
+use tokio::sync::mpsc::error::TrySendError;
+use tonic::Status;
+
+use grpc_tutorial::services::ChatMessage;
+use grpc_tutorial::timestamp;
+
+use crate::record_log::RecordLog;
+
+/// Room used when a client does not name one.
+pub const DEFAULT_ROOM: &str = "lobby";
+
+pub type Outbound = Sender<Result<ChatMessage, Status>>;
+
+/// A chat event as it is stored in the chat log.
+#[derive(Clone, PartialEq, Message)]
+pub struct StoredMessage {
+    #[prost(message, optional, tag = "1")]
+    pub message: Option<ChatMessage>,
+    /// Only this user may see the message; empty means the whole room.
+    #[prost(string, tag = "2")]
+    pub recipient: String,
+}
+
+impl StoredMessage {
+    fn visible_to(&self, room: &str, user_id: &str) -> Option<&ChatMessage> {
+        let message = self.message.as_ref()?;
+        let for_user = self.recipient.is_empty() || self.recipient == user_id;
+        (message.room == room && for_user).then_some(message)
+    }
+}
+
+struct Participant {
+    user_id: String,
+    outbound: Outbound,
+}
+
+struct Inner {
+    rooms: HashMap<String, HashMap<u64, Participant>>,
+    messages: Vec<StoredMessage>,
+}
+
+impl Inner {
+    /// Queues `message` for the participants of `room` it is meant for.
+    ///
+    /// Participants whose queue is full miss the message rather than stalling the
+    /// whole room.
+    fn deliver(
+        &self,
+        room: &str,
+        message: &ChatMessage,
+        recipient: Option<&str>,
+        skip: Option<u64>,
+    ) {
+        let Some(participants) = self.rooms.get(room) else {
+            return;
+        };
+
+        for (id, participant) in participants {
+            if Some(*id) == skip || recipient.is_some_and(|user_id| user_id != participant.user_id)
+            {
+                continue;
+            }
+            match participant.outbound.try_send(Ok(message.clone())) {
+                Ok(()) | Err(TrySendError::Closed(_)) => {}
+                Err(TrySendError::Full(_)) => {
+                    tracing::warn!(
+                        participant = id,
+                        room,
+                        "dropping message for slow participant"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Connected chat participants, grouped by room, and the stored chat history.
+pub struct ChatHub {
+    /// Held by the one message being stored, for as long as the disk write takes.
+    log: Mutex<RecordLog>,
+    /// Held only briefly, so joining, leaving and delivery never wait on the disk.
+    inner: Mutex<Inner>,
+    next_participant: AtomicU64,
+}
+
+impl ChatHub {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let (log, messages) = RecordLog::open(path)?;
+        Ok(ChatHub {
+            log: Mutex::new(log),
+            inner: Mutex::new(Inner {
+                rooms: HashMap::new(),
+                messages,
+            }),
+            next_participant: AtomicU64::new(0),
+        })
+    }
+
+    /// Adds a participant to `room` whose live events go to `live`, and returns the id
+    /// to leave with.
+    ///
+    /// With `resume_after`, the stored events after that id are returned as well. They
+    /// are read under the same lock that registers the participant, so every event ends
+    /// up either in that backlog or in `live`, and the caller should send the backlog
+    /// before forwarding anything from `live`.
+    pub fn join(
+        &self,
+        room: &str,
+        user_id: &str,
+        live: Outbound,
+        resume_after: Option<u64>,
+    ) -> (u64, Vec<ChatMessage>) {
+        let id = self.next_participant.fetch_add(1, Ordering::Relaxed);
+        let mut inner = self.inner.lock().unwrap();
+
+        let backlog = match resume_after {
+            Some(after_id) => Self::visible_after(&inner.messages, room, user_id, after_id)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let participant = Participant {
+            user_id: user_id.to_string(),
+            outbound: live,
+        };
+        inner
+            .rooms
+            .entry(room.to_string())
+            .or_default()
+            .insert(id, participant);
+        (id, backlog)
+    }
+
+    pub fn leave(&self, room: &str, id: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(participants) = inner.rooms.get_mut(room) {
+            participants.remove(&id);
+            if participants.is_empty() {
+                inner.rooms.remove(room);
+            }
+        }
+    }
+
+    /// Sends a transient event such as a typing indicator to everyone in `room` except
+    /// the participant `skip`, without storing it.
+    pub fn broadcast(&self, room: &str, message: &ChatMessage, skip: Option<u64>) {
+        self.inner
+            .lock()
+            .unwrap()
+            .deliver(room, message, None, skip);
+    }
+
+    /// Stores `message` with a fresh id and timestamp, then delivers it to `room`, or
+    /// only to `recipient`'s participants in that room when one is given. The write runs
+    /// on tokio's blocking pool so waiting for the disk does not hold up other tasks.
+    pub async fn publish(
+        self: &Arc<Self>,
+        room: &str,
+        message: ChatMessage,
+        recipient: Option<&str>,
+    ) -> io::Result<ChatMessage> {
+        let hub = self.clone();
+        let room = room.to_string();
+        let recipient = recipient.map(str::to_string);
+        tokio::task::spawn_blocking(move || hub.store(&room, message, recipient.as_deref()))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    fn store(
+        &self,
+        room: &str,
+        mut message: ChatMessage,
+        recipient: Option<&str>,
+    ) -> io::Result<ChatMessage> {
+        // Taken first and held throughout, so ids match positions in the log.
+        let mut log = self.log.lock().unwrap();
+        message.room = room.to_string();
+        message.id = self.inner.lock().unwrap().messages.len() as u64 + 1;
+        message.sent_at = Some(timestamp::now());
+
+        let stored = StoredMessage {
+            message: Some(message.clone()),
+            recipient: recipient.unwrap_or_default().to_string(),
+        };
+        log.append(&stored)?;
+        let mut inner = self.inner.lock().unwrap();
+        inner.messages.push(stored);
+        inner.deliver(room, &message, recipient, None);
+
+        Ok(message)
+    }
+
+    fn visible_after<'a>(
+        messages: &'a [StoredMessage],
+        room: &'a str,
+        user_id: &'a str,
+        after_id: u64,
+    ) -> impl Iterator<Item = &'a ChatMessage> {
+        // Ids are positions in `messages`, starting at 1.
+        let start = usize::try_from(after_id)
+            .unwrap_or(usize::MAX)
+            .min(messages.len());
+        messages[start..]
+            .iter()
+            .filter_map(move |stored| stored.visible_to(room, user_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use grpc_tutorial::services::chat_message::Event;
+    use tokio::sync::mpsc;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("chat_{}_{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn text(user_id: &str, text: &str) -> ChatMessage {
+        ChatMessage {
+            user_id: user_id.to_string(),
+            event: Some(Event::Text(text.to_string())),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn resumed_join_gets_every_message_once_even_past_the_buffer() {
+        let path = temp_path("resume");
+        let hub = Arc::new(ChatHub::open(&path).unwrap());
+        for i in 0..5 {
+            hub.publish("room", text("u1", &i.to_string()), None)
+                .await
+                .unwrap();
+        }
+        hub.publish("room", text("u2", "private"), Some("u2"))
+            .await
+            .unwrap();
+
+        let (live_tx, mut live_rx) = mpsc::channel(1);
+        let (_, backlog) = hub.join("room", "u1", live_tx, Some(1));
+        let ids: Vec<u64> = backlog.iter().map(|message| message.id).collect();
+        assert_eq!(ids, [2, 3, 4, 5]);
+
+        let live = hub.publish("room", text("u1", "live"), None).await.unwrap();
+        assert_eq!(live_rx.recv().await.unwrap().unwrap(), live);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn plain_join_has_no_backlog() {
+        let path = temp_path("plain");
+        let hub = Arc::new(ChatHub::open(&path).unwrap());
+        hub.publish("room", text("u1", "before"), None)
+            .await
+            .unwrap();
+
+        let (live_tx, _live_rx) = mpsc::channel(1);
+        let (_, backlog) = hub.join("room", "u1", live_tx, None);
+        assert!(backlog.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
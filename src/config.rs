@@ -0,0 +1,214 @@
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use serde::Deserialize;
+
+const DEFAULT_PORT: u16 = 50051;
+const DEFAULT_METRICS_PORT: u16 = 9464;
+const DEFAULT_HISTORY_BUFFER: usize = 4;
+const DEFAULT_CHAT_BUFFER: usize = 10;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
+
+/// A gRPC service the server can be told to leave out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceName {
+    Payment,
+    Transaction,
+    Chat,
+    Agent,
+}
+
+impl ServiceName {
+    /// The fully-qualified gRPC name of the service.
+    pub fn grpc_name(self) -> &'static str {
+        match self {
+            ServiceName::Payment => "services.PaymentService",
+            ServiceName::Transaction => "services.TransactionService",
+            ServiceName::Chat => "services.ChatService",
+            ServiceName::Agent => "services.AgentService",
+        }
+    }
+}
+
+/// How the server writes its logs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Multi-line, human-readable output for a terminal.
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// Every setting as it was given, before defaults are applied. The same struct is
+/// parsed from flags (falling back to environment variables) and from the config file.
+#[derive(Debug, Default, Parser, Deserialize)]
+#[command(
+    name = "grpc-server",
+    about = "Serves the payment, transaction and chat services"
+)]
+#[serde(default, deny_unknown_fields)]
+struct Settings {
+    /// TOML file to read settings from; flags and environment variables take precedence
+    #[arg(long, env = "GRPC_SERVER_CONFIG", default_value = "server.toml")]
+    #[serde(skip)]
+    config: PathBuf,
+
+    /// Address to listen on [default: [::1]:50051]
+    #[arg(long, env = "GRPC_LISTEN")]
+    listen: Option<SocketAddr>,
+
+    /// Address to serve Prometheus metrics on, over plain HTTP at /metrics
+    /// [default: [::1]:9464]
+    #[arg(long, env = "GRPC_METRICS_LISTEN")]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Comma-separated services to serve [default: all of them]
+    #[arg(long, env = "GRPC_SERVICES", value_delimiter = ',')]
+    services: Option<Vec<ServiceName>>,
+
+    /// Responses buffered per transaction history stream [default: 4]
+    #[arg(long, env = "GRPC_HISTORY_BUFFER")]
+    history_buffer: Option<NonZeroUsize>,
+
+    /// Messages buffered per chat or agent stream [default: 10]
+    #[arg(long, env = "GRPC_CHAT_BUFFER")]
+    chat_buffer: Option<NonZeroUsize>,
+
+    /// Seconds to let in-flight calls finish after SIGINT or SIGTERM [default: 30]
+    #[arg(long, env = "GRPC_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: Option<u64>,
+
+    /// Log output format [default: pretty]
+    #[arg(long, env = "GRPC_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+
+    /// PEM certificate to serve; together with --tls-key this enables TLS
+    #[arg(long, env = "GRPC_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "GRPC_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM CA that client certificates must be signed by; enables mutual TLS
+    #[arg(long, env = "GRPC_TLS_CLIENT_CA")]
+    tls_client_ca: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Reads settings from a TOML file, treating a missing file as empty.
+    fn read(path: &Path) -> io::Result<Self> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Settings::default()),
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("failed to read {}: {}", path.display(), e),
+                ));
+            }
+        };
+        toml::from_str(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+}
+
+/// Certificate files for serving TLS.
+#[derive(Debug, Clone)]
+pub struct TlsFiles {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: Option<PathBuf>,
+}
+
+/// The server's settings with defaults filled in.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    pub metrics_listen: SocketAddr,
+    pub services: Vec<ServiceName>,
+    pub history_buffer: usize,
+    pub chat_buffer: usize,
+    pub shutdown_timeout: Duration,
+    pub log_format: LogFormat,
+    pub tls: Option<TlsFiles>,
+}
+
+impl ServerConfig {
+    /// Parses the command line and environment, then fills in anything they left out
+    /// from the config file and finally from the defaults.
+    pub fn load() -> io::Result<Self> {
+        let flags = Settings::parse();
+        let file = Settings::read(&flags.config)?;
+
+        let client_ca = flags.tls_client_ca.or(file.tls_client_ca);
+        let tls = match (
+            flags.tls_cert.or(file.tls_cert),
+            flags.tls_key.or(file.tls_key),
+        ) {
+            (Some(cert), Some(key)) => Some(TlsFiles {
+                cert,
+                key,
+                client_ca,
+            }),
+            (None, None) if client_ca.is_some() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "tls_client_ca needs tls_cert and tls_key, since mutual TLS requires TLS",
+                ));
+            }
+            (None, None) => None,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "tls_cert and tls_key must be set together",
+                ));
+            }
+        };
+
+        Ok(ServerConfig {
+            listen: flags
+                .listen
+                .or(file.listen)
+                .unwrap_or(SocketAddr::from((Ipv6Addr::LOCALHOST, DEFAULT_PORT))),
+            metrics_listen: flags.metrics_listen.or(file.metrics_listen).unwrap_or(
+                SocketAddr::from((Ipv6Addr::LOCALHOST, DEFAULT_METRICS_PORT)),
+            ),
+            services: flags
+                .services
+                .or(file.services)
+                .unwrap_or_else(|| ServiceName::value_variants().to_vec()),
+            history_buffer: flags
+                .history_buffer
+                .or(file.history_buffer)
+                .map_or(DEFAULT_HISTORY_BUFFER, NonZeroUsize::get),
+            chat_buffer: flags
+                .chat_buffer
+                .or(file.chat_buffer)
+                .map_or(DEFAULT_CHAT_BUFFER, NonZeroUsize::get),
+            shutdown_timeout: Duration::from_secs(
+                flags
+                    .shutdown_timeout
+                    .or(file.shutdown_timeout)
+                    .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS),
+            ),
+            log_format: flags.log_format.or(file.log_format).unwrap_or_default(),
+            tls,
+        })
+    }
+
+    pub fn serves(&self, service: ServiceName) -> bool {
+        self.services.contains(&service)
+    }
+}
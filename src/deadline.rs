@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tonic::{Request, Status};
+
+use crate::shutdown;
+
+/// Metadata key in which a caller sends how long it will wait for a call.
+const TIMEOUT_KEY: &str = "grpc-timeout";
+
+/// Message of the status that ends a stream once its deadline has passed.
+pub const REASON: &str = "deadline exceeded";
+
+/// When the caller of `request` stops waiting for it, if it sent a timeout. A malformed
+/// timeout is ignored, as tonic does for the calls it times out itself.
+pub fn of<T>(request: &Request<T>) -> Option<Instant> {
+    let timeout = parse(request.metadata().get(TIMEOUT_KEY)?.to_str().ok()?)?;
+    Instant::now().checked_add(timeout)
+}
+
+/// Parses a `grpc-timeout` value: at most eight digits followed by a unit from hours
+/// (`H`) down to nanoseconds (`n`).
+fn parse(value: &str) -> Option<Duration> {
+    if !value.is_ascii() {
+        return None;
+    }
+    let (amount, unit) = value.split_at(value.len().checked_sub(1)?);
+    if amount.is_empty() || amount.len() > 8 || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// Resolves once `deadline` has passed, or never if there is none.
+pub async fn passed(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sends `message` on a stream unless the client goes away or `deadline` passes first,
+/// in which case the stream is ended. Returns whether the message was sent.
+pub async fn send<T>(
+    tx: &mpsc::Sender<Result<T, Status>>,
+    message: T,
+    deadline: Option<Instant>,
+) -> bool {
+    tokio::select! {
+        sent = tx.send(Ok(message)) => sent.is_ok(),
+        _ = tx.closed() => false,
+        _ = passed(deadline) => {
+            end(tx).await;
+            false
+        }
+    }
+}
+
+/// Ends a stream whose deadline has passed with `DEADLINE_EXCEEDED`.
+pub async fn end<T>(tx: &mpsc::Sender<Result<T, Status>>) {
+    shutdown::notify(tx, Err(Status::deadline_exceeded(REASON))).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_unit_is_understood() {
+        assert_eq!(parse("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse("3M"), Some(Duration::from_secs(180)));
+        assert_eq!(parse("30S"), Some(Duration::from_secs(30)));
+        assert_eq!(parse("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(parse("30000000u"), Some(Duration::from_secs(30)));
+        assert_eq!(parse("5n"), Some(Duration::from_nanos(5)));
+    }
+
+    #[test]
+    fn malformed_timeouts_are_rejected() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("S"), None);
+        assert_eq!(parse("123456789S"), None);
+        assert_eq!(parse("+1S"), None);
+        assert_eq!(parse("10s"), None);
+        assert_eq!(parse("1.5S"), None);
+    }
+
+    #[test]
+    fn deadline_comes_from_the_request_metadata() {
+        let mut request = Request::new(());
+        assert_eq!(of(&request), None);
+
+        request.set_timeout(Duration::from_secs(60));
+        let deadline = of(&request).unwrap();
+        assert!(deadline > Instant::now() + Duration::from_secs(59));
+    }
+}
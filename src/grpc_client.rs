@@ -1,42 +1,607 @@
-use tonic::transport::Channel;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use chrono::{DateTime, FixedOffset};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use serde_json::json;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::codegen::InterceptedService;
+use tonic::service::Interceptor;
+use tonic::transport::{Channel, Endpoint};
+use tonic::{Request, Status};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tokio::sync::mpsc::{Sender, Receiver};
+use tokio::sync::mpsc::error::SendError;
 use tokio::io::{self, AsyncBufReadExt};
 
-pub mod services {
-    tonic::include_proto!("services");
+use grpc_tutorial::{timestamp, tls};
+use grpc_tutorial::retry::{self, Backoff, Retries};
+use grpc_tutorial::services::{
+    payment_service_client::PaymentServiceClient, Money, PaymentRequest, PaymentResponse, PaymentStatus,
+    transaction_service_client::TransactionServiceClient, TransactionRequest, TransactionResponse,
+    chat_service_client::ChatServiceClient, ChatMessage, JoinEvent, LeaveEvent,
+    chat_message::Event,
+    agent_service_client::AgentServiceClient, AgentMessage, ClaimSession, ListWaitingSessionsRequest, WaitingSession,
+    agent_message::Action,
+};
+
+/// Remembers the id of the last chat message received per endpoint, user and room, so
+/// the next run can replay whatever it missed. Each line is the tab-separated endpoint,
+/// user, room and id.
+const LAST_CHAT_ID_PATH: &str = ".chat_last_id";
+
+/// Environment variable holding the bearer token sent with every request.
+const TOKEN_ENV: &str = "GRPC_TOKEN";
+
+/// Every setting as it was given; flags fall back to environment variables, which fall
+/// back to the config file.
+#[derive(Debug, Default, Parser, Deserialize)]
+#[command(name = "grpc-client", about = "Talks to grpc-server", subcommand_required = true, arg_required_else_help = true)]
+#[serde(default, deny_unknown_fields)]
+struct Settings {
+    /// TOML file to read settings from; flags and environment variables take precedence
+    #[arg(long, env = "GRPC_CLIENT_CONFIG", default_value = "client.toml")]
+    #[serde(skip)]
+    config: PathBuf,
+
+    /// Server URL [default: http://[::1]:50051, or https:// with --tls-ca]
+    #[arg(long, env = "GRPC_ENDPOINT")]
+    endpoint: Option<String>,
+
+    /// PEM CA to trust; enables TLS
+    #[arg(long, env = "GRPC_TLS_CA")]
+    tls_ca: Option<PathBuf>,
+
+    /// PEM client certificate for mutual TLS, used with --tls-client-key
+    #[arg(long, env = "GRPC_TLS_CLIENT_CERT")]
+    tls_client_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-client-cert
+    #[arg(long, env = "GRPC_TLS_CLIENT_KEY")]
+    tls_client_key: Option<PathBuf>,
+
+    /// Name the server certificate must be valid for [default: localhost]
+    #[arg(long, env = "GRPC_TLS_DOMAIN")]
+    tls_domain: Option<String>,
+
+    /// Seconds to wait for each call before giving up, e.g. 2.5 [default: no limit]
+    #[arg(long, global = true, env = "GRPC_TIMEOUT")]
+    timeout: Option<f64>,
+
+    /// Tries per call, counting the first, while the server cannot be reached [default: 5]
+    #[arg(long, global = true, env = "GRPC_RETRY_ATTEMPTS")]
+    retry_attempts: Option<NonZeroU32>,
+
+    /// Seconds to wait before the first retry, doubling for each one after [default: 0.1]
+    #[arg(long, global = true, env = "GRPC_RETRY_BACKOFF")]
+    retry_backoff: Option<f64>,
+
+    /// Longest wait between retries, in seconds [default: 5]
+    #[arg(long, global = true, env = "GRPC_RETRY_MAX_BACKOFF")]
+    retry_max_backoff: Option<f64>,
+
+    /// Print one JSON object per result instead of text
+    #[arg(long, global = true)]
+    #[serde(skip)]
+    json: bool,
+
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
 }
 
-use services::{
-    payment_service_client::PaymentServiceClient, PaymentRequest,
-    transaction_service_client::TransactionServiceClient, TransactionRequest,
-    chat_service_client::ChatServiceClient, ChatMessage,
-};
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Make a payment
+    Pay(PayArgs),
+    /// Print a user's transaction history
+    History(HistoryArgs),
+    /// Chat in a room, sending each line read from stdin
+    Chat(ChatArgs),
+    /// Pick a waiting customer and chat with them as a support agent
+    Agent(AgentArgs),
+}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = PaymentServiceClient::connect("http://[::1]:50051").await?;
-    let request = tonic::Request::new(PaymentRequest {
-        user_id: "user_123".to_string(),
-        amount: 100.0,
-    });
+#[derive(Debug, Args)]
+struct PayArgs {
+    /// User to pay as
+    #[arg(long, env = "GRPC_USER")]
+    user: String,
+    /// Amount in major units, e.g. 100.00
+    #[arg(long)]
+    amount: String,
+    /// ISO-4217 currency code
+    #[arg(long, default_value = "USD")]
+    currency: String,
+    /// Reuse a key to retry a payment without paying twice [default: a new UUID]
+    #[arg(long)]
+    idempotency_key: Option<String>,
+}
 
-    let response = client.process_payment(request).await?;
-    println!("RESPONSE={:?}", response.into_inner());
+#[derive(Debug, Args)]
+struct HistoryArgs {
+    /// User whose history to read
+    #[arg(long, env = "GRPC_USER")]
+    user: String,
+    /// Only transactions at or after this RFC 3339 time
+    #[arg(long)]
+    since: Option<DateTime<FixedOffset>>,
+    /// Only transactions before this RFC 3339 time
+    #[arg(long)]
+    until: Option<DateTime<FixedOffset>>,
+    /// Only transactions in this status
+    #[arg(long, value_enum)]
+    status: Option<StatusFilter>,
+    /// Smallest amount to include, in major units of --currency
+    #[arg(long)]
+    min_amount: Option<String>,
+    /// Largest amount to include, in major units of --currency
+    #[arg(long)]
+    max_amount: Option<String>,
+    /// Currency of --min-amount and --max-amount
+    #[arg(long, default_value = "USD")]
+    currency: String,
+    /// Stop after this many transactions; 0 prints them all
+    #[arg(long, default_value_t = 0)]
+    page_size: u32,
+    /// Start after the transaction with this cursor
+    #[arg(long)]
+    cursor: Option<String>,
+    /// Keep printing new transactions as they happen
+    #[arg(long, conflicts_with = "page_size")]
+    follow: bool,
+}
 
-    let mut transaction_client = TransactionServiceClient::connect("http://[::1]:50051").await?;
-    let request = tonic::Request::new(TransactionRequest {
-        user_id: "user_123".to_string(),
-    });
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum StatusFilter {
+    Pending,
+    Completed,
+    Declined,
+    Failed,
+}
+
+impl From<StatusFilter> for PaymentStatus {
+    fn from(status: StatusFilter) -> Self {
+        match status {
+            StatusFilter::Pending => PaymentStatus::Pending,
+            StatusFilter::Completed => PaymentStatus::Completed,
+            StatusFilter::Declined => PaymentStatus::Declined,
+            StatusFilter::Failed => PaymentStatus::Failed,
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct ChatArgs {
+    /// User to chat as
+    #[arg(long, env = "GRPC_USER")]
+    user: String,
+    /// Room to join
+    #[arg(long, default_value = "lobby")]
+    room: String,
+}
+
+#[derive(Debug, Args)]
+struct AgentArgs {
+    /// Agent to claim the session as
+    #[arg(long, env = "GRPC_USER")]
+    user: String,
+}
+
+impl Settings {
+    /// Parses the command line and environment, filling in anything they left out from
+    /// the config file, which may be missing.
+    fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        let flags = Settings::parse();
+        let file = match std::fs::read_to_string(&flags.config) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| format!("{}: {}", flags.config.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Settings::default(),
+            Err(e) => return Err(format!("failed to read {}: {}", flags.config.display(), e).into()),
+        };
+
+        let settings = Settings {
+            endpoint: flags.endpoint.or(file.endpoint),
+            tls_ca: flags.tls_ca.or(file.tls_ca),
+            tls_client_cert: flags.tls_client_cert.or(file.tls_client_cert),
+            tls_client_key: flags.tls_client_key.or(file.tls_client_key),
+            tls_domain: flags.tls_domain.or(file.tls_domain),
+            timeout: flags.timeout.or(file.timeout),
+            retry_attempts: flags.retry_attempts.or(file.retry_attempts),
+            retry_backoff: flags.retry_backoff.or(file.retry_backoff),
+            retry_max_backoff: flags.retry_max_backoff.or(file.retry_max_backoff),
+            ..flags
+        };
+        // A client certificate is only sent over TLS, so it is no use without tls_ca.
+        let client_cert_ok = match (&settings.tls_client_cert, &settings.tls_client_key) {
+            (Some(_), Some(_)) => settings.tls_ca.is_some(),
+            (None, None) => true,
+            _ => false,
+        };
+        if !client_cert_ok {
+            return Err("tls_client_cert and tls_client_key must be set together, along with tls_ca".into());
+        }
+        let seconds = [("timeout", settings.timeout), ("retry_backoff", settings.retry_backoff), ("retry_max_backoff", settings.retry_max_backoff)];
+        for (name, value) in seconds {
+            if value.is_some_and(|value| !(value > 0.0 && Duration::try_from_secs_f64(value).is_ok())) {
+                return Err(format!("{} must be a positive number of seconds", name).into());
+            }
+        }
+        Ok(settings)
+    }
+}
+
+impl Settings {
+    /// The server URL, defaulting to the local server with or without TLS.
+    fn endpoint(&self) -> &str {
+        match (&self.endpoint, &self.tls_ca) {
+            (Some(endpoint), _) => endpoint,
+            (None, None) => "http://[::1]:50051",
+            (None, Some(_)) => "https://[::1]:50051",
+        }
+    }
+
+    /// How calls that cannot reach the server are retried.
+    fn backoff(&self) -> Backoff {
+        let default = Backoff::default();
+        Backoff {
+            initial: self.retry_backoff.map_or(default.initial, Duration::from_secs_f64),
+            max: self.retry_max_backoff.map_or(default.max, Duration::from_secs_f64),
+            attempts: self.retry_attempts.map_or(default.attempts, NonZeroU32::get),
+        }
+    }
+}
+
+fn endpoint(settings: &Settings) -> Result<Endpoint, Box<dyn std::error::Error>> {
+    let endpoint = Endpoint::from_shared(settings.endpoint().to_string())?;
+    let Some(ca) = &settings.tls_ca else {
+        return Ok(endpoint);
+    };
+
+    let identity = settings.tls_client_cert.as_deref().zip(settings.tls_client_key.as_deref());
+    let domain = settings.tls_domain.as_deref().unwrap_or("localhost");
+    let tls_config = tls::client_config(Some(ca), identity, domain)?;
+    Ok(endpoint.tls_config(tls_config)?)
+}
+
+/// Attaches the bearer token from `GRPC_TOKEN`, if set, and the `--timeout` deadline to
+/// outgoing requests.
+#[derive(Clone)]
+struct CallOptions {
+    token: Option<MetadataValue<Ascii>>,
+    timeout: Option<Duration>,
+}
+
+impl CallOptions {
+    fn new(settings: &Settings) -> Result<Self, Box<dyn std::error::Error>> {
+        let token = match std::env::var(TOKEN_ENV) {
+            Ok(token) => Some(format!("Bearer {}", token).parse()?),
+            Err(_) => None,
+        };
+        Ok(CallOptions { token, timeout: settings.timeout.map(Duration::from_secs_f64) })
+    }
+}
 
-    let mut stream = transaction_client.get_transaction_history(request).await?.into_inner();
-    while let Some(transaction) = stream.message().await? {
-        println!("Transaction: {:?}", transaction);
+impl Interceptor for CallOptions {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.token {
+            request.metadata_mut().insert("authorization", token.clone());
+        }
+        // Lets the server give up too; streams are also cut off here, see `main`.
+        if let Some(timeout) = self.timeout {
+            request.set_timeout(timeout);
+        }
+        Ok(request)
     }
+}
+
+type Intercepted = InterceptedService<Channel, CallOptions>;
 
-    let channel = Channel::from_static("http://[::1]:50051").connect().await?;
-    let mut client = ChatServiceClient::new(channel);
+/// Makes calls over one channel, retrying those that fail because the server could not
+/// be reached and resuming streams that break where they stopped.
+struct Client {
+    channel: Channel,
+    options: CallOptions,
+    backoff: Backoff,
+}
+
+impl Client {
+    /// Connects to the server, retrying with the settings' backoff while it cannot be
+    /// reached.
+    async fn connect(settings: &Settings, options: CallOptions) -> Result<Self, Box<dyn std::error::Error>> {
+        let endpoint = endpoint(settings)?;
+        let backoff = settings.backoff();
+        let mut retries = Retries::new(backoff);
+        let channel = loop {
+            match endpoint.connect().await {
+                Ok(channel) => break channel,
+                // The transport error itself only says "transport error".
+                Err(e) if retries.wait().await => eprintln!("Failed to connect, retrying: {}", std::error::Error::source(&e).unwrap_or(&e)),
+                Err(e) => return Err(e.into()),
+            }
+        };
+        Ok(Client { channel, options, backoff })
+    }
+
+    fn payments(&self) -> PaymentServiceClient<Intercepted> {
+        PaymentServiceClient::with_interceptor(self.channel.clone(), self.options.clone())
+    }
+
+    fn transactions(&self) -> TransactionServiceClient<Intercepted> {
+        TransactionServiceClient::with_interceptor(self.channel.clone(), self.options.clone())
+    }
+
+    fn chats(&self) -> ChatServiceClient<Intercepted> {
+        ChatServiceClient::with_interceptor(self.channel.clone(), self.options.clone())
+    }
+
+    fn agents(&self) -> AgentServiceClient<Intercepted> {
+        AgentServiceClient::with_interceptor(self.channel.clone(), self.options.clone())
+    }
+
+    /// Makes a payment. It is only retried with an idempotency key, since a lost
+    /// response may belong to a payment the server did make.
+    async fn process_payment(&self, payment: PaymentRequest) -> Result<PaymentResponse, Status> {
+        if payment.idempotency_key.is_empty() {
+            return Ok(self.payments().process_payment(payment).await?.into_inner());
+        }
+        self.backoff.retry(|| {
+            let mut client = self.payments();
+            let payment = payment.clone();
+            async move { Ok(client.process_payment(payment).await?.into_inner()) }
+        }).await
+    }
+
+    async fn list_waiting_sessions(&self) -> Result<Vec<WaitingSession>, Status> {
+        self.backoff.retry(|| {
+            let mut client = self.agents();
+            async move { Ok(client.list_waiting_sessions(ListWaitingSessionsRequest {}).await?.into_inner().sessions) }
+        }).await
+    }
+
+    /// Passes each transaction `request` asks for to `each`. A stream that breaks is
+    /// requested again from the cursor of the last transaction received, so none is
+    /// repeated or skipped.
+    async fn transaction_history(&self, mut request: TransactionRequest, mut each: impl FnMut(&TransactionResponse)) -> Result<(), Status> {
+        let mut client = self.transactions();
+        let mut retries = Retries::new(self.backoff);
+        loop {
+            let status = match client.get_transaction_history(request.clone()).await {
+                Ok(response) => {
+                    let mut stream = response.into_inner();
+                    loop {
+                        match stream.message().await {
+                            Ok(Some(transaction)) => {
+                                retries.reset();
+                                each(&transaction);
+                                request.cursor = transaction.cursor;
+                                if request.page_size > 0 {
+                                    request.page_size -= 1;
+                                    if request.page_size == 0 {
+                                        return Ok(());
+                                    }
+                                }
+                            }
+                            Ok(None) => return Ok(()),
+                            Err(status) => break status,
+                        }
+                    }
+                }
+                Err(status) => status,
+            };
+            if !(retry::is_transient(&status) && retries.wait().await) {
+                return Err(status);
+            }
+            eprintln!("Transaction history interrupted, resuming: {}", status.message());
+        }
+    }
+
+    /// Joins a room with `join` and chats there until the server ends the stream,
+    /// sending each message from `outgoing` and passing each one received to `each`. A
+    /// stream that breaks is joined again with `resume_after_id` set to the last message
+    /// received, so the server replays exactly what was missed.
+    async fn chat(&self, mut join: ChatMessage, outgoing: Receiver<ChatMessage>, mut each: impl FnMut(&ChatMessage) -> std::io::Result<()>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut client = self.chats();
+        let mut retries = Retries::new(self.backoff);
+        let mut outgoing = Some(outgoing);
+        let mut unsent = None;
+        loop {
+            let (tx, rx) = mpsc::channel(32);
+            tx.send(join.clone()).await?;
+            let forward = tokio::spawn(forward_chat(outgoing.take().ok_or("chat input was lost")?, unsent.take(), tx));
+
+            let status = match client.chat(ReceiverStream::new(rx)).await {
+                Ok(response) => {
+                    let mut stream = response.into_inner();
+                    loop {
+                        match stream.message().await {
+                            Ok(Some(message)) => {
+                                retries.reset();
+                                each(&message)?;
+                                if message.id > 0 {
+                                    join.event = Some(Event::Join(JoinEvent { resume_after_id: Some(message.id) }));
+                                }
+                            }
+                            Ok(None) => return Ok(()),
+                            Err(status) => break status,
+                        }
+                    }
+                }
+                Err(status) => status,
+            };
+            // The broken call has dropped its request stream, which ends the forwarder.
+            (outgoing, unsent) = forward.await.map(|(outgoing, unsent)| (Some(outgoing), unsent))?;
+            if !(retry::is_transient(&status) && retries.wait().await) {
+                return Err(status.into());
+            }
+            eprintln!("Chat interrupted, rejoining: {}", status.message());
+        }
+    }
+}
+
+/// Forwards chat messages from `outgoing` to one call's request stream `tx` until either
+/// ends, starting with `unsent` if an earlier call broke before taking it. Returns the
+/// input along with any message the call broke before taking, for the next call.
+async fn forward_chat(mut outgoing: Receiver<ChatMessage>, unsent: Option<ChatMessage>, tx: Sender<ChatMessage>) -> (Receiver<ChatMessage>, Option<ChatMessage>) {
+    if let Some(message) = unsent
+        && let Err(SendError(message)) = tx.send(message).await
+    {
+        return (outgoing, Some(message));
+    }
+    loop {
+        let message = tokio::select! {
+            message = outgoing.recv() => message,
+            _ = tx.closed() => return (outgoing, None),
+        };
+        // Dropping `tx` once the input runs out ends the request stream.
+        let Some(message) = message else {
+            return (outgoing, None);
+        };
+        if let Err(SendError(message)) = tx.send(message).await {
+            return (outgoing, Some(message));
+        }
+    }
+}
+
+fn money_json(money: &Option<Money>) -> serde_json::Value {
+    match money {
+        Some(money) => json!({ "currency_code": money.currency_code, "minor_units": money.minor_units, "display": money.to_string() }),
+        None => serde_json::Value::Null,
+    }
+}
+
+fn timestamp_json(timestamp: &Option<prost_types::Timestamp>) -> serde_json::Value {
+    timestamp.as_ref().and_then(timestamp::to_datetime).map(|at| json!(at.to_rfc3339())).unwrap_or_default()
+}
+
+fn print_payment(response: &PaymentResponse, as_json: bool) {
+    let status = PaymentStatus::from_i32(response.status).unwrap_or_default();
+    if as_json {
+        println!("{}", json!({
+            "success": response.success,
+            "transaction_id": response.transaction_id,
+            "status": format!("{:?}", status),
+            "decline_code": response.decline_code,
+            "processed_at": timestamp_json(&response.processed_at),
+        }));
+    } else if response.decline_code.is_empty() {
+        println!("Payment {}: {:?}", response.transaction_id, status);
+    } else {
+        println!("Payment {}: {:?} ({})", response.transaction_id, status, response.decline_code);
+    }
+}
+
+fn print_transaction(transaction: &TransactionResponse, as_json: bool) {
+    if as_json {
+        println!("{}", json!({
+            "transaction_id": transaction.transaction_id,
+            "status": transaction.status,
+            "amount": money_json(&transaction.amount),
+            "cursor": transaction.cursor,
+            "timestamp": timestamp_json(&transaction.timestamp),
+        }));
+        return;
+    }
+
+    let amount = transaction.amount.as_ref().map(Money::to_string).unwrap_or_default();
+    let recorded_at = transaction.timestamp.as_ref().and_then(timestamp::to_datetime);
+    match recorded_at {
+        Some(recorded_at) => println!("Transaction: {} {} {} at {}", transaction.transaction_id, transaction.status, amount, recorded_at.to_rfc3339()),
+        None => println!("Transaction: {} {} {}", transaction.transaction_id, transaction.status, amount),
+    }
+}
+
+fn print_chat_message(message: &ChatMessage, as_json: bool) {
+    if !as_json {
+        println!("Server says: {:?}", message);
+        return;
+    }
+
+    let event = match &message.event {
+        Some(Event::Text(text)) => json!({ "type": "text", "text": text }),
+        Some(Event::Join(_)) => json!({ "type": "join" }),
+        Some(Event::Leave(leave)) => json!({ "type": "leave", "reason": leave.reason }),
+        Some(Event::Typing(typing)) => json!({ "type": "typing", "typing": typing.typing }),
+        None => serde_json::Value::Null,
+    };
+    println!("{}", json!({
+        "id": message.id,
+        "room": message.room,
+        "user_id": message.user_id,
+        "sent_at": timestamp_json(&message.sent_at),
+        "event": event,
+    }));
+}
+
+async fn run_pay(client: Client, args: PayArgs, as_json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let payment = PaymentRequest {
+        user_id: args.user,
+        amount: Some(Money::parse(&args.amount, &args.currency)?),
+        idempotency_key: args.idempotency_key.unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+    };
+
+    let response = client.process_payment(payment).await?;
+    print_payment(&response, as_json);
+    Ok(())
+}
+
+async fn run_history(client: Client, args: HistoryArgs, as_json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let bound = |amount: Option<String>| amount.map(|amount| Money::parse(&amount, &args.currency)).transpose();
+    let request = TransactionRequest {
+        user_id: args.user,
+        start_time: args.since.map(|at| timestamp::from_millis(at.timestamp_millis())),
+        end_time: args.until.map(|at| timestamp::from_millis(at.timestamp_millis())),
+        status: args.status.map_or(PaymentStatus::Unspecified, PaymentStatus::from) as i32,
+        min_amount: bound(args.min_amount)?,
+        max_amount: bound(args.max_amount)?,
+        page_size: args.page_size,
+        cursor: args.cursor.unwrap_or_default(),
+        follow: args.follow,
+    };
+
+    client.transaction_history(request, |transaction| print_transaction(transaction, as_json)).await?;
+    Ok(())
+}
+
+/// Key of a chat session's line in `LAST_CHAT_ID_PATH`.
+fn chat_session_key(endpoint: &str, user: &str, room: &str) -> String {
+    format!("{}\t{}\t{}\t", endpoint, user, room)
+}
+
+/// Returns the last chat message id stored for `key`, if any.
+fn last_chat_id(key: &str) -> Option<u64> {
+    let contents = std::fs::read_to_string(LAST_CHAT_ID_PATH).ok()?;
+    contents.lines().find_map(|line| line.strip_prefix(key)?.trim().parse().ok())
+}
+
+/// Stores `id` as the last chat message id for `key`, keeping other sessions' ids.
+fn save_last_chat_id(key: &str, id: u64) -> std::io::Result<()> {
+    let contents = match std::fs::read_to_string(LAST_CHAT_ID_PATH) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    let mut lines: Vec<String> = contents
+        .lines()
+        .filter(|line| !line.starts_with(key) && line.split('\t').count() == 4)
+        .map(str::to_string)
+        .collect();
+    lines.push(format!("{}{}", key, id));
+    std::fs::write(LAST_CHAT_ID_PATH, lines.join("\n") + "\n")
+}
+
+async fn run_chat(client: Client, endpoint: &str, args: ChatArgs, as_json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let session_key = chat_session_key(endpoint, &args.user, &args.room);
+    let event_message = move |event| ChatMessage {
+        user_id: args.user.clone(),
+        room: args.room.clone(),
+        event: Some(event),
+        ..Default::default()
+    };
+    // Pick up where the previous session stopped, if there was one.
+    let join = event_message(Event::Join(JoinEvent { resume_after_id: last_chat_id(&session_key) }));
     let (tx, rx): (Sender<ChatMessage>, Receiver<ChatMessage>) = mpsc::channel(32);
 
     tokio::spawn(async move {
@@ -47,24 +612,102 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             if line.trim().is_empty() {
                 continue;
             }
-            let message = ChatMessage {
-                user_id: "user_123".to_string(),
-                message: line,
-            };
 
-            if tx.send(message).await.is_err() {
+            if tx.send(event_message(Event::Text(line))).await.is_err() {
                 eprintln!("Failed to send message to server.");
-                break;
+                return;
             }
         }
+
+        let _ = tx.send(event_message(Event::Leave(LeaveEvent { reason: "left".to_string() }))).await;
     });
 
-    let request = tonic::Request::new(ReceiverStream::new(rx));
-    let mut response_stream = client.chat(request).await?.into_inner();
+    client.chat(join, rx, |message| {
+        print_chat_message(message, as_json);
+        if message.id > 0 {
+            save_last_chat_id(&session_key, message.id)?;
+        }
+        Ok(())
+    }).await
+}
+
+/// Support agent mode: pick a waiting customer and chat with them until stdin closes.
+async fn run_agent(client: Client, args: AgentArgs, as_json: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let sessions = client.list_waiting_sessions().await?;
+    if as_json {
+        let sessions: Vec<_> = sessions
+            .iter()
+            .map(|session| json!({
+                "session_id": session.session_id,
+                "room": session.room,
+                "user_id": session.user_id,
+                "first_message": session.first_message,
+            }))
+            .collect();
+        println!("{}", json!({ "waiting_sessions": sessions }));
+    } else {
+        for session in &sessions {
+            println!("[{}] {} in {}: {}", session.session_id, session.user_id, session.room, session.first_message);
+        }
+    }
+    if sessions.is_empty() {
+        eprintln!("No customers are waiting.");
+        return Ok(());
+    }
+
+    eprintln!("Session to claim:");
+    let mut reader = io::BufReader::new(io::stdin()).lines();
+    let session_id = reader.next_line().await?.unwrap_or_default().trim().parse()?;
+
+    let (tx, rx): (Sender<AgentMessage>, Receiver<AgentMessage>) = mpsc::channel(32);
+    let agent_message = move |action| AgentMessage {
+        agent_id: args.user.clone(),
+        action: Some(action),
+    };
+    tx.send(agent_message(Action::Claim(ClaimSession { session_id }))).await?;
 
+    tokio::spawn(async move {
+        while let Ok(Some(line)) = reader.next_line().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if tx.send(agent_message(Action::Text(line))).await.is_err() {
+                eprintln!("Failed to send message to server.");
+                break;
+            }
+        }
+    });
+
+    let mut response_stream = client.agents().agent_chat(ReceiverStream::new(rx)).await?.into_inner();
     while let Some(response) = response_stream.message().await? {
-        println!("Server says: {:?}", response);
+        print_chat_message(&response, as_json);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut settings = Settings::load()?;
+    let options = CallOptions::new(&settings)?;
+    let timeout = options.timeout;
+    let client = Client::connect(&settings, options).await?;
+
+    let command = settings.command.take().ok_or("a subcommand is required")?;
+    let run = async {
+        match command {
+            Command::Pay(args) => run_pay(client, args, settings.json).await,
+            Command::History(args) => run_history(client, args, settings.json).await,
+            Command::Chat(args) => run_chat(client, settings.endpoint(), args, settings.json).await,
+            Command::Agent(args) => run_agent(client, args, settings.json).await,
+        }
+    };
+    // tonic only times calls out until the response starts, so streams need a deadline
+    // of their own.
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, run)
+            .await
+            .unwrap_or_else(|_| Err(Status::deadline_exceeded(format!("call did not finish within {:?}", timeout)).into())),
+        None => run.await,
+    }
+}
@@ -1,20 +1,100 @@
-use tonic::{transport::Server, Request, Response, Status};
+// `tonic::Status` is large, but it is the error type every handler has to return.
+#![allow(clippy::result_large_err)]
+
+use std::io;
+use std::sync::Arc;
+
+use tonic::{transport::{NamedService, Server}, Request, Response, Status};
 use tokio::sync::mpsc;
 use tokio_stream::wrappers::ReceiverStream;
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::Instrument;
 
-pub mod services {
-    tonic::include_proto!("services");
-}
+mod auth;
+mod chat;
+mod config;
+mod deadline;
+mod handoff;
+mod health;
+mod ledger;
+mod metrics;
+mod policy;
+mod record_log;
+mod reflection;
+mod responder;
+mod shutdown;
+mod telemetry;
+mod validation;
 
-use services::{
-    payment_service_server::{PaymentService, PaymentServiceServer}, PaymentRequest, PaymentResponse,
+use grpc_tutorial::{money, timestamp, tls};
+use grpc_tutorial::grpc::health::v1::{health_check_response::ServingStatus, health_server::HealthServer};
+use grpc_tutorial::grpc::reflection::v1alpha::server_reflection_server::ServerReflectionServer;
+use grpc_tutorial::services::{
+    payment_service_server::{PaymentService, PaymentServiceServer}, PaymentRequest, PaymentResponse, PaymentStatus,
     transaction_service_server::{TransactionService, TransactionServiceServer}, TransactionRequest, TransactionResponse,
-    chat_service_server::{ChatService, ChatServiceServer}, ChatMessage,
+    chat_service_server::{ChatService, ChatServiceServer}, ChatMessage, JoinEvent, LeaveEvent,
+    chat_message::Event,
+    agent_service_server::{AgentService, AgentServiceServer}, AgentMessage, ListWaitingSessionsRequest, ListWaitingSessionsResponse,
+    agent_message::Action,
 };
+use auth::Authenticator;
+use chat::ChatHub;
+use config::{ServerConfig, ServiceName};
+use handoff::HandoffQueue;
+use health::{HealthReporter, HealthService};
+use ledger::{Ledger, LedgerEntry};
+use metrics::Metrics;
+use policy::Policy;
+use reflection::ReflectionService;
+use responder::{BOT_USER_ID, ChatResponder, ResponderConfig};
+use shutdown::Shutdown;
+use telemetry::TraceLayer;
+
+const LEDGER_PATH: &str = "ledger.log";
+const CHAT_LOG_PATH: &str = "chat.log";
+const RESPONDER_CONFIG_PATH: &str = "responder.toml";
+const TOKENS_PATH: &str = "tokens.toml";
+const POLICY_PATH: &str = "policy.toml";
+
+/// Payments above this many major units of their currency are declined.
+const MAX_PAYMENT_MAJOR_UNITS: i64 = 10_000_000;
+
+impl From<LedgerEntry> for PaymentResponse {
+    fn from(entry: LedgerEntry) -> Self {
+        PaymentResponse {
+            success: entry.status == PaymentStatus::Completed as i32,
+            processed_at: Some(timestamp::from_millis(entry.created_at_millis)),
+            transaction_id: entry.transaction_id,
+            status: entry.status,
+            decline_code: entry.decline_code,
+        }
+    }
+}
+
+impl From<LedgerEntry> for TransactionResponse {
+    fn from(entry: LedgerEntry) -> Self {
+        TransactionResponse {
+            status: entry.status_label().to_string(),
+            transaction_id: entry.transaction_id,
+            amount: entry.amount,
+            cursor: entry.sequence.to_string(),
+            timestamp: Some(timestamp::from_millis(entry.created_at_millis)),
+        }
+    }
+}
+
+pub struct MyPaymentService {
+    ledger: Arc<Ledger>,
+    health: HealthReporter,
+    metrics: Metrics,
+}
 
-#[derive(Default)]
-pub struct MyPaymentService {}
+impl MyPaymentService {
+    pub fn new(ledger: Arc<Ledger>, health: HealthReporter, metrics: Metrics) -> Self {
+        MyPaymentService { ledger, health, metrics }
+    }
+}
 
 #[tonic::async_trait]
 impl PaymentService for MyPaymentService {
@@ -22,16 +102,70 @@ impl PaymentService for MyPaymentService {
         &self,
         request: Request<PaymentRequest>,
     ) -> Result<Response<PaymentResponse>, Status> {
-        println!("Received payment request: {:?}", request);
+        let access = policy::access(&request)?;
+        let payment = request.into_inner();
+        tracing::info!(user_id = payment.user_id.as_str(), amount = ?payment.amount, "payment requested");
+        validation::validate_payment(&payment)?;
+        access.check_user(&payment.user_id)?;
+
+        let amount = payment.amount.unwrap_or_default();
+        let limit = money::exponent(&amount.currency_code)
+            .and_then(|exponent| MAX_PAYMENT_MAJOR_UNITS.checked_mul(10i64.pow(exponent)))
+            .unwrap_or(i64::MAX);
+        let (status, decline_code) = if amount.minor_units > limit {
+            (PaymentStatus::Declined, "AMOUNT_LIMIT_EXCEEDED")
+        } else {
+            (PaymentStatus::Completed, "")
+        };
+
+        let entry = LedgerEntry {
+            user_id: payment.user_id,
+            amount: Some(amount),
+            idempotency_key: payment.idempotency_key,
+            status: status as i32,
+            decline_code: decline_code.to_string(),
+            ..Default::default()
+        };
+        let recorded = match self.ledger.record_payment(entry.clone()).await {
+            Ok(recorded) => recorded,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to record payment");
+                // Both services read and write the same ledger.
+                self.health.set(ServiceName::Payment, ServingStatus::NotServing);
+                self.health.set(ServiceName::Transaction, ServingStatus::NotServing);
+                self.metrics.payment(PaymentStatus::Failed as i32);
+                return Ok(Response::new(PaymentResponse {
+                    success: false,
+                    transaction_id: String::new(),
+                    status: PaymentStatus::Failed as i32,
+                    decline_code: "LEDGER_UNAVAILABLE".to_string(),
+                    processed_at: Some(timestamp::now()),
+                }));
+            }
+        };
+        if recorded.amount != entry.amount {
+            return Err(Status::failed_precondition(
+                "idempotency_key was already used for a different payment",
+            ));
+        }
 
-        // Process the request and return a response
-        // This example immediately returns a successful result for demonstration purposes
-        Ok(Response::new(PaymentResponse { success: true }))
+        self.metrics.payment(recorded.status);
+        Ok(Response::new(recorded.into()))
     }
 }
 
-#[derive(Default)]
-pub struct MyTransactionService {}
+pub struct MyTransactionService {
+    ledger: Arc<Ledger>,
+    buffer: usize,
+    shutdown: Shutdown,
+    metrics: Metrics,
+}
+
+impl MyTransactionService {
+    pub fn new(ledger: Arc<Ledger>, buffer: usize, shutdown: Shutdown, metrics: Metrics) -> Self {
+        MyTransactionService { ledger, buffer, shutdown, metrics }
+    }
+}
 
 #[tonic::async_trait]
 impl TransactionService for MyTransactionService {
@@ -41,31 +175,150 @@ impl TransactionService for MyTransactionService {
         &self,
         request: Request<TransactionRequest>,
     ) -> Result<Response<Self::GetTransactionHistoryStream>, Status> {
-        println!("Received transaction history request: {:?}", request);
-        let (tx, rx): (Sender<Result<TransactionResponse, Status>>, Receiver<Result<TransactionResponse, Status>>) = mpsc::channel(4);
+        let access = policy::access(&request)?;
+        let mut query = validation::history_query(request.get_ref())?;
+        tracing::info!(user_id = query.user_id.as_str(), follow = request.get_ref().follow, "history requested");
+        access.check_user(&query.user_id)?;
+        let deadline = deadline::of(&request);
+        // Subscribe before reading the history so nothing recorded in between is missed.
+        let mut updates = request.get_ref().follow.then(|| self.ledger.subscribe());
+        let history = self.ledger.history(&query);
+        let ledger = self.ledger.clone();
+        let mut shutdown = self.shutdown.clone();
+        let (tx, rx): (Sender<Result<TransactionResponse, Status>>, Receiver<Result<TransactionResponse, Status>>) = mpsc::channel(self.buffer);
+        let active = self.metrics.stream_opened("/services.TransactionService/GetTransactionHistory");
 
         tokio::spawn(async move {
-            for i in 0..30 {
-                if tx.send(Ok(TransactionResponse {
-                    transaction_id: format!("trans_{}", i),
-                    status: "Completed".to_string(),
-                    amount: 100.0,
-                    timestamp: "2022-01-01T12:00:00Z".to_string(),
-                })).await.is_err() {
-                    break;
+            let _active = active;
+            for entry in history {
+                query.after_sequence = entry.sequence;
+                tracing::debug!(transaction_id = entry.transaction_id.as_str(), "sending transaction");
+                if !deadline::send(&tx, entry.into(), deadline).await {
+                    return;
                 }
-                if i % 10 == 9 {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            }
+
+            let Some(updates) = updates.as_mut() else {
+                return;
+            };
+            query.limit = None;
+            loop {
+                let received = tokio::select! {
+                    received = updates.recv() => received,
+                    _ = tx.closed() => return,
+                    _ = deadline::passed(deadline) => {
+                        deadline::end(&tx).await;
+                        return;
+                    }
+                    _ = shutdown.wait() => {
+                        shutdown::notify(&tx, Err(Status::unavailable(shutdown::REASON))).await;
+                        return;
+                    }
+                };
+                let entries = match received {
+                    Ok(entry) => vec![entry],
+                    // Fell too far behind the broadcast; catch up from the ledger instead.
+                    Err(RecvError::Lagged(_)) => ledger.history(&query),
+                    Err(RecvError::Closed) => return,
+                };
+
+                for entry in entries {
+                    if !query.matches(&entry) {
+                        continue;
+                    }
+                    query.after_sequence = entry.sequence;
+                    tracing::debug!(transaction_id = entry.transaction_id.as_str(), "sending transaction");
+                    if !deadline::send(&tx, entry.into(), deadline).await {
+                        return;
+                    }
                 }
             }
-        });
+        }.instrument(tracing::Span::current()));
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
 }
 
-#[derive(Default)]
-pub struct MyChatService {}
+pub struct MyChatService {
+    hub: Arc<ChatHub>,
+    responder: Arc<dyn ChatResponder>,
+    handoff: Arc<HandoffQueue>,
+    buffer: usize,
+    shutdown: Shutdown,
+    health: HealthReporter,
+    metrics: Metrics,
+}
+
+impl MyChatService {
+    pub fn new(hub: Arc<ChatHub>, responder: Arc<dyn ChatResponder>, handoff: Arc<HandoffQueue>, buffer: usize, shutdown: Shutdown, health: HealthReporter, metrics: Metrics) -> Self {
+        MyChatService { hub, responder, handoff, buffer, shutdown, health, metrics }
+    }
+}
+
+/// Logs a chat message that could not be stored and reports the chat services as not
+/// serving, since the chat log they share is failing.
+fn chat_store_failed(health: &HealthReporter, e: io::Error) {
+    tracing::error!(error = %e, "failed to store chat message");
+    health.set(ServiceName::Chat, ServingStatus::NotServing);
+    health.set(ServiceName::Agent, ServingStatus::NotServing);
+}
+
+/// Names the kind of a chat event for logs, which leave message text out.
+fn event_kind(event: &Option<Event>) -> &'static str {
+    match event {
+        Some(Event::Text(_)) => "text",
+        Some(Event::Join(_)) => "join",
+        Some(Event::Leave(_)) => "leave",
+        Some(Event::Typing(_)) => "typing",
+        None => "none",
+    }
+}
+
+/// Joins the room named by the first event on a chat stream and returns the room and
+/// participant id. A join event asking for a replay gets the stored history first, and
+/// live events are forwarded to `tx` only once all of it has been sent.
+async fn join_room(hub: &Arc<ChatHub>, health: &HealthReporter, tx: &chat::Outbound, buffer: usize, first: &ChatMessage) -> (String, u64) {
+    let room = if first.room.is_empty() {
+        chat::DEFAULT_ROOM.to_string()
+    } else {
+        first.room.clone()
+    };
+
+    let resume_after = match first.event {
+        Some(Event::Join(JoinEvent { resume_after_id })) => resume_after_id,
+        _ => None,
+    };
+    let (live_tx, mut live_rx) = mpsc::channel(buffer);
+    let (id, backlog) = hub.join(&room, &first.user_id, live_tx, resume_after);
+    let tx = tx.clone();
+    tokio::spawn(async move {
+        for message in backlog {
+            tracing::debug!(id = message.id, user_id = message.user_id.as_str(), event = event_kind(&message.event), "replaying chat message");
+            if tx.send(Ok(message)).await.is_err() {
+                return;
+            }
+        }
+        while let Some(message) = live_rx.recv().await {
+            if let Ok(message) = &message {
+                tracing::debug!(id = message.id, user_id = message.user_id.as_str(), event = event_kind(&message.event), "sending chat message");
+            }
+            if tx.send(message).await.is_err() {
+                break;
+            }
+        }
+    }.instrument(tracing::Span::current()));
+
+    let joined = ChatMessage {
+        user_id: first.user_id.clone(),
+        event: Some(Event::Join(JoinEvent::default())),
+        ..Default::default()
+    };
+    if let Err(e) = hub.publish(&room, joined, None).await {
+        chat_store_failed(health, e);
+    }
+
+    (room, id)
+}
 
 #[tonic::async_trait]
 impl ChatService for MyChatService {
@@ -75,19 +328,231 @@ impl ChatService for MyChatService {
         &self,
         request: Request<tonic::Streaming<ChatMessage>>,
     ) -> Result<Response<Self::ChatStream>, Status> {
+        let access = policy::access(&request)?;
+        let deadline = deadline::of(&request);
+        let mut stream = request.into_inner();
+        let (tx, rx) = mpsc::channel(self.buffer);
+        let hub = self.hub.clone();
+        let responder = self.responder.clone();
+        let handoff = self.handoff.clone();
+        let buffer = self.buffer;
+        let mut shutdown = self.shutdown.clone();
+        let health = self.health.clone();
+        self.metrics.watch_chat_queue(&tx);
+        let active = self.metrics.stream_opened("/services.ChatService/Chat");
+
+        tokio::spawn(async move {
+            let _active = active;
+            // (room, participant id, user id) once the first event has joined a room
+            let mut joined: Option<(String, u64, String)> = None;
+            let reason = loop {
+                let next = tokio::select! {
+                    next = stream.message() => next,
+                    _ = tx.closed() => break "disconnected".to_string(),
+                    _ = deadline::passed(deadline) => {
+                        deadline::end(&tx).await;
+                        break deadline::REASON.to_string();
+                    }
+                    _ = shutdown.wait() => {
+                        // Tell the participant why the stream is about to end.
+                        let (room, user_id) = joined.as_ref().map(|(room, _, user_id)| (room.clone(), user_id.clone())).unwrap_or_default();
+                        let notice = ChatMessage {
+                            user_id,
+                            room,
+                            event: Some(Event::Leave(LeaveEvent { reason: shutdown::REASON.to_string() })),
+                            ..Default::default()
+                        };
+                        shutdown::notify(&tx, Ok(notice)).await;
+                        shutdown::notify(&tx, Err(Status::unavailable(shutdown::REASON))).await;
+                        break shutdown::REASON.to_string();
+                    }
+                };
+                let message = match next {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break "disconnected".to_string(),
+                    Err(status) => break format!("stream error: {}", status.message()),
+                };
+                tracing::debug!(user_id = message.user_id.as_str(), event = event_kind(&message.event), "received chat message");
+                if let Err(status) = access.check_user(&message.user_id) {
+                    let _ = tx.send(Err(status)).await;
+                    break "permission denied".to_string();
+                }
+                let Some(event) = message.event.clone() else {
+                    continue;
+                };
+
+                let (room, id, user_id) = match &joined {
+                    Some(joined) => joined.clone(),
+                    None => {
+                        let (room, id) = join_room(&hub, &health, &tx, buffer, &message).await;
+                        joined.insert((room, id, message.user_id.clone())).clone()
+                    }
+                };
+
+                let stored = match event {
+                    Event::Join(_) => continue,
+                    Event::Leave(leave) => break leave.reason,
+                    Event::Typing(_) => {
+                        let typing = ChatMessage { room: room.clone(), ..message };
+                        hub.broadcast(&room, &typing, Some(id));
+                        continue;
+                    }
+                    Event::Text(text) => {
+                        // Customers talking to a human agent get no bot replies.
+                        let reply = if handoff.is_claimed(&room, &user_id) {
+                            None
+                        } else {
+                            responder.respond(&room, &user_id, &text).await
+                        };
+                        let reply = reply.map(|reply| ChatMessage {
+                            user_id: BOT_USER_ID.to_string(),
+                            event: Some(Event::Text(reply)),
+                            ..Default::default()
+                        });
+                        match (hub.publish(&room, message, None).await, reply) {
+                            (Ok(_), Some(reply)) => hub.publish(&room, reply, Some(&user_id)).await.map(|_| ()),
+                            (stored, _) => stored.map(|_| ()),
+                        }
+                    }
+                };
+                if let Err(e) = stored {
+                    chat_store_failed(&health, e);
+                }
+            };
+
+            if let Some((room, id, user_id)) = joined {
+                hub.leave(&room, id);
+                handoff.cancel(&room, &user_id);
+                let left = ChatMessage {
+                    user_id,
+                    event: Some(Event::Leave(LeaveEvent { reason })),
+                    ..Default::default()
+                };
+                if let Err(e) = hub.publish(&room, left, None).await {
+                    chat_store_failed(&health, e);
+                }
+            }
+        }.instrument(tracing::Span::current()));
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+pub struct MyAgentService {
+    hub: Arc<ChatHub>,
+    handoff: Arc<HandoffQueue>,
+    buffer: usize,
+    shutdown: Shutdown,
+    health: HealthReporter,
+    metrics: Metrics,
+}
+
+impl MyAgentService {
+    pub fn new(hub: Arc<ChatHub>, handoff: Arc<HandoffQueue>, buffer: usize, shutdown: Shutdown, health: HealthReporter, metrics: Metrics) -> Self {
+        MyAgentService { hub, handoff, buffer, shutdown, health, metrics }
+    }
+}
+
+#[tonic::async_trait]
+impl AgentService for MyAgentService {
+    type AgentChatStream = ReceiverStream<Result<ChatMessage, Status>>;
+
+    async fn list_waiting_sessions(
+        &self,
+        _request: Request<ListWaitingSessionsRequest>,
+    ) -> Result<Response<ListWaitingSessionsResponse>, Status> {
+        Ok(Response::new(ListWaitingSessionsResponse {
+            sessions: self.handoff.waiting(),
+        }))
+    }
+
+    async fn agent_chat(
+        &self,
+        request: Request<tonic::Streaming<AgentMessage>>,
+    ) -> Result<Response<Self::AgentChatStream>, Status> {
+        let access = policy::access(&request)?;
         let mut stream = request.into_inner();
-        let (tx, rx) = mpsc::channel(10);
+        let first = stream
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("expected a claim before the stream ended"))?;
+        let Some(Action::Claim(claim)) = first.action else {
+            return Err(Status::invalid_argument("the first AgentMessage must claim a session"));
+        };
+        if first.agent_id.trim().is_empty() {
+            return Err(Status::invalid_argument("agent_id must not be empty"));
+        }
+        access.check_user(&first.agent_id)?;
+        let agent_id = first.agent_id;
+        let session = self.handoff.claim(claim.session_id, &agent_id).ok_or_else(|| {
+            Status::failed_precondition("session is not waiting; another agent may have claimed it")
+        })?;
+        tracing::info!(session_id = session.session_id, customer = session.user_id.as_str(), "agent claimed session");
+
+        let (tx, rx) = mpsc::channel(self.buffer);
+        self.metrics.watch_chat_queue(&tx);
+        let (room_tx, mut room_rx) = mpsc::channel(self.buffer);
+        let hub = self.hub.clone();
+        let handoff = self.handoff.clone();
+        let mut shutdown = self.shutdown.clone();
+        let health = self.health.clone();
+        let (participant, _) = hub.join(&session.room, &agent_id, room_tx, None);
+        let announce = move |event| ChatMessage {
+            user_id: agent_id.clone(),
+            event: Some(event),
+            ..Default::default()
+        };
+        if let Err(e) = hub.publish(&session.room, announce(Event::Join(JoinEvent::default())), Some(&session.user_id)).await {
+            chat_store_failed(&health, e);
+        }
+
+        // Only the customer's own events are relayed; the room may be shared with others.
+        let customer = session.user_id.clone();
+        let agent_tx = tx.clone();
+        tokio::spawn(async move {
+            while let Some(message) = room_rx.recv().await {
+                if matches!(&message, Ok(message) if message.user_id != customer) {
+                    continue;
+                }
+                if let Ok(message) = &message {
+                    tracing::debug!(id = message.id, event = event_kind(&message.event), "relaying customer message");
+                }
+                if tx.send(message).await.is_err() {
+                    break;
+                }
+            }
+        }.instrument(tracing::Span::current()));
 
         tokio::spawn(async move {
-            while let Some(message) = stream.message().await.unwrap_or_else(|_| None) {
-                println!("Received message: {:?}", message);
-                let reply = ChatMessage {
-                    user_id: message.user_id.clone(),
-                    message: format!("Terima kasih telah melakukan chat kepada CS virtual, Pesan anda akan dibalas pada jam kerja. pesan: {}", message.message),
+            let reason = loop {
+                let next = tokio::select! {
+                    next = stream.message() => next,
+                    _ = shutdown.wait() => {
+                        shutdown::notify(&agent_tx, Err(Status::unavailable(shutdown::REASON))).await;
+                        break shutdown::REASON.to_string();
+                    }
                 };
-                tx.send(Ok(reply)).await.unwrap_or_else(|_| {});
+                match next {
+                    Ok(Some(AgentMessage { action: Some(Action::Text(text)), .. })) => {
+                        tracing::debug!("received agent reply");
+                        let reply = announce(Event::Text(text));
+                        if let Err(e) = hub.publish(&session.room, reply, Some(&session.user_id)).await {
+                            chat_store_failed(&health, e);
+                        }
+                    }
+                    Ok(Some(_)) => tracing::warn!("ignoring agent message without text"),
+                    Ok(None) => break "agent disconnected".to_string(),
+                    Err(status) => break format!("stream error: {}", status.message()),
+                }
+            };
+
+            hub.leave(&session.room, participant);
+            handoff.release(session.session_id);
+            let left = announce(Event::Leave(LeaveEvent { reason }));
+            if let Err(e) = hub.publish(&session.room, left, Some(&session.user_id)).await {
+                chat_store_failed(&health, e);
             }
-        });
+        }.instrument(tracing::Span::current()));
 
         Ok(Response::new(ReceiverStream::new(rx)))
     }
@@ -95,17 +560,67 @@ impl ChatService for MyChatService {
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let addr = "[::1]:50051".parse()?;
-    let payment_service = MyPaymentService::default();
-    let transaction_service = MyTransactionService::default();
-    let chat_service = MyChatService::default();
-
-    Server::builder()
-        .add_service(PaymentServiceServer::new(payment_service))
-        .add_service(TransactionServiceServer::new(transaction_service))
-        .add_service(ChatServiceServer::new(chat_service))
-        .serve(addr)
-        .await?;
+    let config = ServerConfig::load()?;
+    telemetry::init(config.log_format);
+    let (start_shutdown, shutdown) = Shutdown::new();
+    let health = HealthReporter::new(&config);
+    let metrics = Metrics::new()?;
+    let ledger = Arc::new(Ledger::open(LEDGER_PATH)?);
+    let payment_service = MyPaymentService::new(ledger.clone(), health.clone(), metrics.clone());
+    let transaction_service = MyTransactionService::new(ledger, config.history_buffer, shutdown.clone(), metrics.clone());
+    let chat_hub = Arc::new(ChatHub::open(CHAT_LOG_PATH)?);
+    let handoff_queue = Arc::new(HandoffQueue::default());
+    let responder = ResponderConfig::load(RESPONDER_CONFIG_PATH)?.build(handoff_queue.clone());
+    let chat_service = MyChatService::new(chat_hub.clone(), responder.into(), handoff_queue.clone(), config.chat_buffer, shutdown.clone(), health.clone(), metrics.clone());
+    let agent_service = MyAgentService::new(chat_hub, handoff_queue, config.chat_buffer, shutdown.clone(), health.clone(), metrics.clone());
+    let health_service = HealthService::new(health.clone(), shutdown.clone());
+    let mut served: Vec<String> = config.services.iter().map(|service| service.grpc_name().to_string()).collect();
+    served.push(<HealthServer<HealthService> as NamedService>::NAME.to_string());
+    served.push(<ServerReflectionServer<ReflectionService> as NamedService>::NAME.to_string());
+    let reflection_service = ReflectionService::new(grpc_tutorial::FILE_DESCRIPTOR_SET, served, shutdown.clone())?;
+    let authenticator = Authenticator::load(TOKENS_PATH)?;
+    let policy = Policy::load(POLICY_PATH)?;
+
+    let mut server = Server::builder();
+    if let Some(tls) = &config.tls {
+        let tls_config = tls::server_config(&tls.cert, &tls.key, tls.client_ca.as_deref())?;
+        server = server.tls_config(tls_config)?;
+    }
+
+    let serve_metrics = metrics.clone().serve(config.metrics_listen)?;
+    tokio::spawn(async move {
+        if let Err(e) = serve_metrics.await {
+            tracing::error!(error = %e, "metrics server failed");
+        }
+    });
+
+    tracing::info!(listen = %config.listen, metrics_listen = %config.metrics_listen, services = ?config.services, "listening");
+    // Once shutdown starts the server stops accepting connections and waits for open
+    // calls; streams that would otherwise stay open end themselves via `Shutdown`.
+    let mut stopping = shutdown.clone();
+    let serve = server
+        .layer(TraceLayer::new(metrics))
+        // Probes carry no token, so health checks skip authentication.
+        .add_service(HealthServer::new(health_service))
+        .add_optional_service(config.serves(ServiceName::Payment).then(|| policy::protect(PaymentServiceServer::new(payment_service), &authenticator, &policy)))
+        .add_optional_service(config.serves(ServiceName::Transaction).then(|| policy::protect(TransactionServiceServer::new(transaction_service), &authenticator, &policy)))
+        .add_optional_service(config.serves(ServiceName::Chat).then(|| policy::protect(ChatServiceServer::new(chat_service), &authenticator, &policy)))
+        .add_optional_service(config.serves(ServiceName::Agent).then(|| policy::protect(AgentServiceServer::new(agent_service), &authenticator, &policy)))
+        .add_service(policy::protect(ServerReflectionServer::new(reflection_service), &authenticator, &policy))
+        .serve_with_shutdown(config.listen, async move { stopping.wait().await });
+    tokio::pin!(serve);
+
+    tokio::select! {
+        result = &mut serve => return Ok(result?),
+        _ = shutdown::signal() => {}
+    }
+    tracing::info!(timeout = ?config.shutdown_timeout, "shutting down; waiting for open calls");
+    health.set_all_not_serving();
+    let _ = start_shutdown.send(true);
+    match tokio::time::timeout(config.shutdown_timeout, serve).await {
+        Ok(result) => result?,
+        Err(_) => tracing::warn!(timeout = ?config.shutdown_timeout, "calls still open; exiting anyway"),
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
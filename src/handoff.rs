@@ -0,0 +1,91 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+use grpc_tutorial::services::WaitingSession;
+use grpc_tutorial::timestamp;
+
+/// Customers waiting for a human agent, and the sessions agents have claimed.
+#[derive(Default)]
+pub struct HandoffQueue {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Oldest first.
+    waiting: VecDeque<WaitingSession>,
+    /// Claimed sessions by id, with the id of the agent handling them.
+    claimed: HashMap<u64, (WaitingSession, String)>,
+    next_id: u64,
+}
+
+impl Inner {
+    fn is_known(&self, room: &str, user_id: &str) -> bool {
+        let matches = |session: &WaitingSession| session.room == room && session.user_id == user_id;
+        self.waiting.iter().any(matches)
+            || self.claimed.values().any(|(session, _)| matches(session))
+    }
+}
+
+impl HandoffQueue {
+    /// Queues the customer unless they are already waiting or being helped in that room;
+    /// returns whether they were added.
+    pub fn enqueue(&self, room: &str, user_id: &str, first_message: &str) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_known(room, user_id) {
+            return false;
+        }
+
+        inner.next_id += 1;
+        let session = WaitingSession {
+            session_id: inner.next_id,
+            room: room.to_string(),
+            user_id: user_id.to_string(),
+            first_message: first_message.to_string(),
+            waiting_since: Some(timestamp::now()),
+        };
+        inner.waiting.push_back(session);
+        true
+    }
+
+    pub fn waiting(&self) -> Vec<WaitingSession> {
+        self.inner.lock().unwrap().waiting.iter().cloned().collect()
+    }
+
+    /// Hands a waiting session to `agent_id`, or returns `None` if it is not waiting,
+    /// for example because another agent claimed it first.
+    pub fn claim(&self, session_id: u64, agent_id: &str) -> Option<WaitingSession> {
+        let mut inner = self.inner.lock().unwrap();
+        let index = inner
+            .waiting
+            .iter()
+            .position(|session| session.session_id == session_id)?;
+        let session = inner.waiting.remove(index)?;
+        inner
+            .claimed
+            .insert(session_id, (session.clone(), agent_id.to_string()));
+        Some(session)
+    }
+
+    /// Ends a claimed session once its agent is done.
+    pub fn release(&self, session_id: u64) {
+        self.inner.lock().unwrap().claimed.remove(&session_id);
+    }
+
+    /// Whether an agent is currently handling the customer in `room`.
+    pub fn is_claimed(&self, room: &str, user_id: &str) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .claimed
+            .values()
+            .any(|(session, _)| session.room == room && session.user_id == user_id)
+    }
+
+    /// Drops the customer from the queue, e.g. because they left before an agent came.
+    pub fn cancel(&self, room: &str, user_id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .waiting
+            .retain(|session| session.room != room || session.user_id != user_id);
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use clap::ValueEnum;
+use tokio::sync::{mpsc, watch};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+
+use grpc_tutorial::grpc::health::v1::health_check_response::ServingStatus;
+use grpc_tutorial::grpc::health::v1::health_server::Health;
+use grpc_tutorial::grpc::health::v1::{HealthCheckRequest, HealthCheckResponse};
+
+use crate::config::{ServerConfig, ServiceName};
+use crate::shutdown::{self, Shutdown};
+
+/// Serving status of the whole server, under the empty service name, and of each of
+/// its services. Clones share the same statuses.
+#[derive(Debug, Clone, Default)]
+pub struct HealthReporter {
+    statuses: Arc<Mutex<HashMap<String, watch::Sender<ServingStatus>>>>,
+}
+
+impl HealthReporter {
+    /// Reports the server as serving, along with the services `config` enables.
+    pub fn new(config: &ServerConfig) -> Self {
+        let reporter = HealthReporter::default();
+        reporter.set_named("", ServingStatus::Serving);
+        for service in ServiceName::value_variants() {
+            let status = if config.serves(*service) {
+                ServingStatus::Serving
+            } else {
+                ServingStatus::NotServing
+            };
+            reporter.set(*service, status);
+        }
+        reporter
+    }
+
+    pub fn set(&self, service: ServiceName, status: ServingStatus) {
+        self.set_named(service.grpc_name(), status);
+    }
+
+    /// Reports the server and every service as not serving, e.g. once shutdown starts.
+    pub fn set_all_not_serving(&self) {
+        for status in self.statuses.lock().unwrap().values() {
+            // Names that are only being watched stay unknown.
+            status.send_if_modified(|status| {
+                let known = *status != ServingStatus::ServiceUnknown;
+                if known {
+                    *status = ServingStatus::NotServing;
+                }
+                known
+            });
+        }
+    }
+
+    fn set_named(&self, name: &str, status: ServingStatus) {
+        let mut statuses = self.statuses.lock().unwrap();
+        match statuses.get(name) {
+            Some(current) => {
+                current.send_replace(status);
+            }
+            None => {
+                statuses.insert(name.to_string(), watch::channel(status).0);
+            }
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<ServingStatus> {
+        let statuses = self.statuses.lock().unwrap();
+        let status = *statuses.get(name)?.borrow();
+        (status != ServingStatus::ServiceUnknown).then_some(status)
+    }
+
+    /// Follows the status of `name`, which starts as `SERVICE_UNKNOWN` if it has none yet.
+    fn subscribe(&self, name: &str) -> watch::Receiver<ServingStatus> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| watch::channel(ServingStatus::ServiceUnknown).0)
+            .subscribe()
+    }
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse {
+        status: status as i32,
+    }
+}
+
+/// Answers `grpc.health.v1.Health` checks from a `HealthReporter`.
+pub struct HealthService {
+    reporter: HealthReporter,
+    shutdown: Shutdown,
+}
+
+impl HealthService {
+    pub fn new(reporter: HealthReporter, shutdown: Shutdown) -> Self {
+        HealthService { reporter, shutdown }
+    }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    type WatchStream = ReceiverStream<Result<HealthCheckResponse, Status>>;
+
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = &request.get_ref().service;
+        match self.reporter.get(service) {
+            Some(status) => Ok(Response::new(response(status))),
+            None => Err(Status::not_found(format!("unknown service {:?}", service))),
+        }
+    }
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let mut statuses = self.reporter.subscribe(&request.get_ref().service);
+        let mut shutdown = self.shutdown.clone();
+        let (tx, rx) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            loop {
+                let status = *statuses.borrow_and_update();
+                if tx.send(Ok(response(status))).await.is_err() {
+                    return;
+                }
+                tokio::select! {
+                    changed = statuses.changed() => {
+                        if changed.is_err() {
+                            return;
+                        }
+                    }
+                    _ = tx.closed() => return,
+                    _ = shutdown.wait() => {
+                        // Pass on the NOT_SERVING set as shutdown started before ending.
+                        if statuses.has_changed().unwrap_or(false) {
+                            let status = *statuses.borrow_and_update();
+                            shutdown::notify(&tx, Ok(response(status))).await;
+                        }
+                        shutdown::notify(&tx, Err(Status::unavailable(shutdown::REASON))).await;
+                        return;
+                    }
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reporter() -> HealthReporter {
+        let reporter = HealthReporter::default();
+        reporter.set_named("", ServingStatus::Serving);
+        reporter.set(ServiceName::Payment, ServingStatus::Serving);
+        reporter
+    }
+
+    #[test]
+    fn statuses_are_reported_by_grpc_name() {
+        let reporter = reporter();
+        assert_eq!(reporter.get(""), Some(ServingStatus::Serving));
+        assert_eq!(
+            reporter.get("services.PaymentService"),
+            Some(ServingStatus::Serving)
+        );
+        assert_eq!(reporter.get("services.Unknown"), None);
+    }
+
+    #[test]
+    fn watching_an_unknown_service_does_not_make_it_known() {
+        let reporter = reporter();
+        let statuses = reporter.subscribe("services.Unknown");
+        assert_eq!(*statuses.borrow(), ServingStatus::ServiceUnknown);
+        assert_eq!(reporter.get("services.Unknown"), None);
+    }
+
+    #[test]
+    fn shutdown_marks_everything_not_serving() {
+        let reporter = reporter();
+        let mut statuses = reporter.subscribe("services.PaymentService");
+        reporter.set_all_not_serving();
+        assert!(statuses.has_changed().unwrap());
+        assert_eq!(*statuses.borrow_and_update(), ServingStatus::NotServing);
+        assert_eq!(reporter.get(""), Some(ServingStatus::NotServing));
+    }
+}
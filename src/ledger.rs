@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use prost::Message;
+use tokio::sync::broadcast;
+
+use grpc_tutorial::services::{Money, PaymentStatus};
+
+use crate::record_log::RecordLog;
+
+/// A payment as it is stored in the ledger file.
+#[derive(Clone, PartialEq, Message)]
+pub struct LedgerEntry {
+    #[prost(string, tag = "1")]
+    pub transaction_id: String,
+    #[prost(string, tag = "2")]
+    pub user_id: String,
+    #[prost(int64, tag = "5")]
+    pub created_at_millis: i64,
+    #[prost(string, tag = "6")]
+    pub idempotency_key: String,
+    #[prost(enumeration = "PaymentStatus", tag = "7")]
+    pub status: i32,
+    #[prost(string, tag = "8")]
+    pub decline_code: String,
+    #[prost(message, optional, tag = "9")]
+    pub amount: Option<Money>,
+    /// Position in the ledger, starting at 1.
+    #[prost(uint64, tag = "10")]
+    pub sequence: u64,
+}
+
+impl LedgerEntry {
+    /// Human-readable status, as shown in `TransactionResponse.status`.
+    pub fn status_label(&self) -> &'static str {
+        status_label(self.status)
+    }
+}
+
+/// Human-readable name of a `PaymentStatus` value.
+pub fn status_label(status: i32) -> &'static str {
+    match PaymentStatus::from_i32(status) {
+        Some(PaymentStatus::Pending) => "Pending",
+        Some(PaymentStatus::Completed) => "Completed",
+        Some(PaymentStatus::Declined) => "Declined",
+        Some(PaymentStatus::Failed) => "Failed",
+        Some(PaymentStatus::Unspecified) | None => "Unknown",
+    }
+}
+
+/// Which entries `Ledger::history` returns.
+#[derive(Debug, Default, Clone)]
+pub struct HistoryQuery {
+    pub user_id: String,
+    /// Only entries with a greater sequence number.
+    pub after_sequence: u64,
+    pub start_millis: Option<i64>,
+    pub end_millis: Option<i64>,
+    pub status: Option<PaymentStatus>,
+    pub min_amount: Option<Money>,
+    pub max_amount: Option<Money>,
+    pub limit: Option<usize>,
+}
+
+impl HistoryQuery {
+    pub fn matches(&self, entry: &LedgerEntry) -> bool {
+        let amount = entry.amount.as_ref();
+        let within = |bound: &Option<Money>, ok: fn(i64, i64) -> bool| match (bound, amount) {
+            (None, _) => true,
+            (Some(bound), Some(amount)) => {
+                bound.currency_code == amount.currency_code
+                    && ok(amount.minor_units, bound.minor_units)
+            }
+            (Some(_), None) => false,
+        };
+
+        entry.user_id == self.user_id
+            && entry.sequence > self.after_sequence
+            && self
+                .start_millis
+                .is_none_or(|start| entry.created_at_millis >= start)
+            && self
+                .end_millis
+                .is_none_or(|end| entry.created_at_millis < end)
+            && self
+                .status
+                .is_none_or(|status| entry.status == status as i32)
+            && within(&self.min_amount, |amount, min| amount >= min)
+            && within(&self.max_amount, |amount, max| amount <= max)
+    }
+}
+
+/// How many new entries a slow follower may fall behind before it has to catch up
+/// from the ledger itself.
+const UPDATES_CAPACITY: usize = 256;
+
+/// Append-only payment ledger shared by the payment and transaction services.
+///
+/// Entries are written to a `RecordLog` and kept in memory so history reads never
+/// touch the file.
+pub struct Ledger {
+    /// Held by the one payment being written, for as long as the disk write takes.
+    log: Mutex<RecordLog>,
+    /// Held only briefly, so history reads never wait on the disk.
+    inner: Mutex<Inner>,
+    updates: broadcast::Sender<LedgerEntry>,
+}
+
+struct Inner {
+    entries: Vec<LedgerEntry>,
+    /// Index into `entries` keyed by `(user_id, idempotency_key)`.
+    idempotency: HashMap<(String, String), usize>,
+}
+
+impl Inner {
+    fn push(&mut self, entry: LedgerEntry) {
+        if !entry.idempotency_key.is_empty() {
+            let key = (entry.user_id.clone(), entry.idempotency_key.clone());
+            self.idempotency.insert(key, self.entries.len());
+        }
+        self.entries.push(entry);
+    }
+}
+
+impl Ledger {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let (log, entries) = RecordLog::open(path)?;
+        let mut inner = Inner {
+            entries: Vec::new(),
+            idempotency: HashMap::new(),
+        };
+        for entry in entries {
+            inner.push(entry);
+        }
+
+        let (updates, _) = broadcast::channel(UPDATES_CAPACITY);
+        Ok(Ledger {
+            log: Mutex::new(log),
+            inner: Mutex::new(inner),
+            updates,
+        })
+    }
+
+    /// Durably appends a payment and returns the stored entry, with its transaction id
+    /// and creation time filled in.
+    ///
+    /// If the entry carries an idempotency key that its user already used, nothing is
+    /// written and the original entry is returned instead. The write runs on tokio's
+    /// blocking pool so waiting for the disk does not hold up other tasks.
+    pub async fn record_payment(self: &Arc<Self>, entry: LedgerEntry) -> io::Result<LedgerEntry> {
+        let ledger = self.clone();
+        tokio::task::spawn_blocking(move || ledger.write_payment(entry))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    fn write_payment(&self, mut entry: LedgerEntry) -> io::Result<LedgerEntry> {
+        // Taken first and held throughout, so payments reach the log in sequence order
+        // and a retry cannot slip in between the idempotency check and the write.
+        let mut log = self.log.lock().unwrap();
+        {
+            let inner = self.inner.lock().unwrap();
+            if !entry.idempotency_key.is_empty() {
+                let key = (entry.user_id.clone(), entry.idempotency_key.clone());
+                if let Some(&index) = inner.idempotency.get(&key) {
+                    return Ok(inner.entries[index].clone());
+                }
+            }
+            entry.sequence = inner.entries.len() as u64 + 1;
+        }
+        entry.transaction_id = format!("trans_{}", entry.sequence);
+        entry.created_at_millis = chrono::Utc::now().timestamp_millis();
+
+        log.append(&entry)?;
+        self.inner.lock().unwrap().push(entry.clone());
+        let _ = self.updates.send(entry.clone());
+
+        Ok(entry)
+    }
+
+    /// Receives every entry recorded from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<LedgerEntry> {
+        self.updates.subscribe()
+    }
+
+    /// Returns the entries matching `query`, oldest first.
+    pub fn history(&self, query: &HistoryQuery) -> Vec<LedgerEntry> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .entries
+            .iter()
+            .filter(|entry| query.matches(entry))
+            .take(query.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ledger_{}_{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn payment(user_id: &str, key: &str, minor_units: i64) -> LedgerEntry {
+        LedgerEntry {
+            user_id: user_id.to_string(),
+            idempotency_key: key.to_string(),
+            status: PaymentStatus::Completed as i32,
+            amount: Some(Money {
+                currency_code: "USD".to_string(),
+                minor_units,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn all_of(user_id: &str) -> HistoryQuery {
+        HistoryQuery {
+            user_id: user_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn payments_get_sequential_ids_and_survive_reopening() {
+        let path = temp_path("reopen");
+        let ledger = Arc::new(Ledger::open(&path).unwrap());
+        let first = ledger.record_payment(payment("u1", "", 100)).await.unwrap();
+        let second = ledger.record_payment(payment("u2", "", 200)).await.unwrap();
+        assert_eq!(
+            (first.sequence, first.transaction_id.as_str()),
+            (1, "trans_1")
+        );
+        assert_eq!(
+            (second.sequence, second.transaction_id.as_str()),
+            (2, "trans_2")
+        );
+        drop(ledger);
+
+        let ledger = Ledger::open(&path).unwrap();
+        assert_eq!(ledger.history(&all_of("u1")), [first]);
+        assert_eq!(ledger.history(&all_of("u2")), [second]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reused_idempotency_key_returns_the_original_entry() {
+        let path = temp_path("idempotency");
+        let ledger = Arc::new(Ledger::open(&path).unwrap());
+        let original = ledger
+            .record_payment(payment("u1", "k", 100))
+            .await
+            .unwrap();
+        let retried = ledger
+            .record_payment(payment("u1", "k", 100))
+            .await
+            .unwrap();
+        let other_user = ledger
+            .record_payment(payment("u2", "k", 100))
+            .await
+            .unwrap();
+
+        assert_eq!(retried, original);
+        assert_ne!(other_user.transaction_id, original.transaction_id);
+        assert_eq!(ledger.history(&all_of("u1")).len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recorded_payments_are_broadcast() {
+        let path = temp_path("broadcast");
+        let ledger = Arc::new(Ledger::open(&path).unwrap());
+        let mut updates = ledger.subscribe();
+        let recorded = ledger.record_payment(payment("u1", "", 100)).await.unwrap();
+        assert_eq!(updates.recv().await.unwrap(), recorded);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
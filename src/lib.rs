@@ -0,0 +1,35 @@
+pub mod money;
+pub mod retry;
+pub mod timestamp;
+pub mod tls;
+
+pub mod services {
+    tonic::include_proto!("services");
+}
+
+/// Encoded `FileDescriptorSet` of every proto file compiled into this crate, along with
+/// the files they import.
+pub const FILE_DESCRIPTOR_SET: &[u8] = tonic::include_file_descriptor_set!("services_descriptor");
+
+/// Standard error model types, for rich error details on statuses.
+pub mod google {
+    pub mod rpc {
+        tonic::include_proto!("google.rpc");
+    }
+}
+
+/// Standard gRPC services: health checking for load balancers and orchestrators, and
+/// server reflection for generic tools.
+pub mod grpc {
+    pub mod health {
+        pub mod v1 {
+            tonic::include_proto!("grpc.health.v1");
+        }
+    }
+
+    pub mod reflection {
+        pub mod v1alpha {
+            tonic::include_proto!("grpc.reflection.v1alpha");
+        }
+    }
+}
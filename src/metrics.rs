@@ -0,0 +1,260 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode, header};
+use prometheus::core::Collector;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use tokio::sync::mpsc::WeakSender;
+use tonic::{Code, Status};
+
+use grpc_tutorial::services::ChatMessage;
+
+use crate::chat;
+use crate::ledger;
+
+/// Path the metrics are served on.
+const METRICS_PATH: &str = "/metrics";
+
+/// The sending half of a chat or agent stream, held weakly so it can still close.
+type ChatQueue = WeakSender<Result<ChatMessage, Status>>;
+
+/// Counters and gauges describing what the server has been doing, in the Prometheus
+/// text format. Clones share the same values.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    handled: IntCounterVec,
+    latency: HistogramVec,
+    active_streams: IntGaugeVec,
+    payments: IntCounterVec,
+    chat_queue_depth: IntGauge,
+    chat_queues: Arc<Mutex<Vec<ChatQueue>>>,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let metrics = Metrics {
+            registry: Registry::new(),
+            handled: IntCounterVec::new(
+                Opts::new(
+                    "grpc_server_handled_total",
+                    "RPCs completed, by method and status code",
+                ),
+                &["grpc_method", "grpc_code"],
+            )?,
+            latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "grpc_server_handling_seconds",
+                    "Time from receiving an RPC until its status was sent",
+                ),
+                &["grpc_method"],
+            )?,
+            active_streams: IntGaugeVec::new(
+                Opts::new(
+                    "grpc_server_active_streams",
+                    "Response streams currently open",
+                ),
+                &["grpc_method"],
+            )?,
+            payments: IntCounterVec::new(
+                Opts::new("payments_total", "Payment responses, by status"),
+                &["status"],
+            )?,
+            chat_queue_depth: IntGauge::new(
+                "chat_queue_depth",
+                "Messages waiting to be sent to chat and agent clients",
+            )?,
+            chat_queues: Arc::default(),
+        };
+        let collectors: [Box<dyn Collector>; 5] = [
+            Box::new(metrics.handled.clone()),
+            Box::new(metrics.latency.clone()),
+            Box::new(metrics.active_streams.clone()),
+            Box::new(metrics.payments.clone()),
+            Box::new(metrics.chat_queue_depth.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+        Ok(metrics)
+    }
+
+    /// Counts a finished RPC. Calls to methods that do not exist share one label, so
+    /// made-up paths cannot add series without limit.
+    pub fn rpc_finished(&self, method: &str, code: Code, latency: Duration) {
+        let method = if code == Code::Unimplemented {
+            "unknown"
+        } else {
+            method
+        };
+        let code = format!("{:?}", code);
+        self.handled.with_label_values(&[method, &code]).inc();
+        self.latency
+            .with_label_values(&[method])
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Counts a stream of `method` as open until the returned guard is dropped.
+    pub fn stream_opened(&self, method: &str) -> ActiveStream {
+        let gauge = self.active_streams.with_label_values(&[method]);
+        gauge.inc();
+        ActiveStream(gauge)
+    }
+
+    /// Counts a payment response with `status`, a `PaymentStatus` value.
+    pub fn payment(&self, status: i32) {
+        self.payments
+            .with_label_values(&[ledger::status_label(status)])
+            .inc();
+    }
+
+    /// Includes the messages waiting in `tx` in the chat queue depth for as long as the
+    /// stream it feeds is open.
+    pub fn watch_chat_queue(&self, tx: &chat::Outbound) {
+        self.chat_queues.lock().unwrap().push(tx.downgrade());
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    fn encode(&self) -> String {
+        let mut depth = 0;
+        self.chat_queues
+            .lock()
+            .unwrap()
+            .retain(|queue| match queue.upgrade() {
+                Some(tx) => {
+                    depth += tx.max_capacity() - tx.capacity();
+                    true
+                }
+                None => false,
+            });
+        self.chat_queue_depth.set(depth as i64);
+
+        let mut buffer = Vec::new();
+        // Encoding only fails for metrics without a name, which are never registered.
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    fn respond(&self, request: &Request<Body>) -> Response<Body> {
+        let mut response = Response::default();
+        if request.uri().path() != METRICS_PATH {
+            *response.status_mut() = StatusCode::NOT_FOUND;
+        } else if request.method() != Method::GET {
+            *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+        } else {
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                header::HeaderValue::from_static(prometheus::TEXT_FORMAT),
+            );
+            *response.body_mut() = Body::from(self.encode());
+        }
+        response
+    }
+
+    /// Binds `addr` and returns a future that answers `GET /metrics` on it over plain
+    /// HTTP, so a bad address fails startup rather than the first scrape.
+    pub fn serve(self, addr: SocketAddr) -> hyper::Result<impl Future<Output = hyper::Result<()>>> {
+        let make_service = make_service_fn(move |_| {
+            let metrics = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let response = metrics.respond(&request);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+        Ok(hyper::Server::try_bind(&addr)?.serve(make_service))
+    }
+}
+
+/// Keeps a stream counted in `grpc_server_active_streams` until dropped.
+pub struct ActiveStream(IntGauge);
+
+impl Drop for ActiveStream {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use grpc_tutorial::services::PaymentStatus;
+    use tokio::sync::mpsc;
+
+    fn line<'a>(text: &'a str, series: &str) -> Option<&'a str> {
+        text.lines().find(|line| line.starts_with(series))
+    }
+
+    #[test]
+    fn payments_are_counted_by_status() {
+        let metrics = Metrics::new().unwrap();
+        metrics.payment(PaymentStatus::Completed as i32);
+        metrics.payment(PaymentStatus::Completed as i32);
+        metrics.payment(PaymentStatus::Failed as i32);
+        let text = metrics.encode();
+        assert_eq!(
+            line(&text, "payments_total{status=\"Completed\"}"),
+            Some("payments_total{status=\"Completed\"} 2")
+        );
+        assert_eq!(
+            line(&text, "payments_total{status=\"Failed\"}"),
+            Some("payments_total{status=\"Failed\"} 1")
+        );
+    }
+
+    #[test]
+    fn unknown_methods_share_a_label() {
+        let metrics = Metrics::new().unwrap();
+        metrics.rpc_finished("/made.Up/One", Code::Unimplemented, Duration::ZERO);
+        metrics.rpc_finished("/made.Up/Two", Code::Unimplemented, Duration::ZERO);
+        let text = metrics.encode();
+        assert!(!text.contains("made.Up"));
+        assert!(text.contains(
+            "grpc_server_handled_total{grpc_code=\"Unimplemented\",grpc_method=\"unknown\"} 2"
+        ));
+    }
+
+    #[test]
+    fn active_streams_drop_when_the_guard_does() {
+        let metrics = Metrics::new().unwrap();
+        let series = "grpc_server_active_streams{grpc_method=\"/services.ChatService/Chat\"}";
+        let stream = metrics.stream_opened("/services.ChatService/Chat");
+        assert_eq!(
+            line(&metrics.encode(), series),
+            Some(&*format!("{} 1", series))
+        );
+        drop(stream);
+        assert_eq!(
+            line(&metrics.encode(), series),
+            Some(&*format!("{} 0", series))
+        );
+    }
+
+    #[test]
+    fn chat_queue_depth_counts_open_streams_only() {
+        let metrics = Metrics::new().unwrap();
+        let (tx, _rx) = mpsc::channel(4);
+        tx.try_send(Ok(ChatMessage::default())).unwrap();
+        tx.try_send(Ok(ChatMessage::default())).unwrap();
+        metrics.watch_chat_queue(&tx);
+        let (closed, _closed_rx) = mpsc::channel(4);
+        closed.try_send(Ok(ChatMessage::default())).unwrap();
+        metrics.watch_chat_queue(&closed);
+        drop(closed);
+
+        assert_eq!(
+            line(&metrics.encode(), "chat_queue_depth "),
+            Some("chat_queue_depth 2")
+        );
+        assert_eq!(metrics.chat_queues.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+
+use crate::services::Money;
+
+/// ISO-4217 currencies we accept, with the number of digits after the decimal point.
+const CURRENCIES: &[(&str, u32)] = &[
+    ("AUD", 2),
+    ("BHD", 3),
+    ("CHF", 2),
+    ("CNY", 2),
+    ("EUR", 2),
+    ("GBP", 2),
+    ("IDR", 2),
+    ("JPY", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("MYR", 2),
+    ("SGD", 2),
+    ("USD", 2),
+];
+
+/// Returns the minor-unit exponent of `currency_code`, or `None` if it is not supported.
+pub fn exponent(currency_code: &str) -> Option<u32> {
+    CURRENCIES
+        .iter()
+        .find(|(code, _)| *code == currency_code)
+        .map(|(_, exponent)| *exponent)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    UnknownCurrency(String),
+    InvalidAmount(String),
+    TooManyDecimals { currency_code: String, exponent: u32 },
+    Overflow,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::UnknownCurrency(code) => write!(f, "unsupported currency {:?}", code),
+            MoneyError::InvalidAmount(amount) => write!(f, "{:?} is not a decimal amount", amount),
+            MoneyError::TooManyDecimals { currency_code, exponent } => write!(
+                f,
+                "{} amounts have at most {} decimal places",
+                currency_code, exponent
+            ),
+            MoneyError::Overflow => write!(f, "amount is too large"),
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
+
+impl Money {
+    /// Parses a decimal string such as `"100.50"` into minor units of `currency_code`,
+    /// rejecting more decimal places than the currency has.
+    pub fn parse(amount: &str, currency_code: &str) -> Result<Money, MoneyError> {
+        let exponent = exponent(currency_code)
+            .ok_or_else(|| MoneyError::UnknownCurrency(currency_code.to_string()))?;
+        let invalid = || MoneyError::InvalidAmount(amount.to_string());
+
+        let (negative, digits) = match amount.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, amount),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+        if whole.is_empty() || !whole.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) || digits.ends_with('.') {
+            return Err(invalid());
+        }
+        if fraction.len() > exponent as usize {
+            return Err(MoneyError::TooManyDecimals {
+                currency_code: currency_code.to_string(),
+                exponent,
+            });
+        }
+
+        let padded = format!("{}{:0<width$}", whole, fraction, width = exponent as usize);
+        let minor_units: i64 = padded.parse().map_err(|_| MoneyError::Overflow)?;
+
+        Ok(Money {
+            currency_code: currency_code.to_string(),
+            minor_units: if negative { -minor_units } else { minor_units },
+        })
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let exponent = exponent(&self.currency_code).unwrap_or(0);
+        let sign = if self.minor_units < 0 { "-" } else { "" };
+        let units = self.minor_units.unsigned_abs();
+
+        if exponent == 0 {
+            return write!(f, "{}{} {}", sign, units, self.currency_code);
+        }
+        let scale = 10u64.pow(exponent);
+        write!(
+            f,
+            "{}{}.{:0width$} {}",
+            sign,
+            units / scale,
+            units % scale,
+            self.currency_code,
+            width = exponent as usize
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minor_units(amount: &str, currency_code: &str) -> Result<i64, MoneyError> {
+        Money::parse(amount, currency_code).map(|money| money.minor_units)
+    }
+
+    #[test]
+    fn decimals_are_scaled_by_the_currency_exponent() {
+        assert_eq!(minor_units("100.5", "USD"), Ok(10050));
+        assert_eq!(minor_units("1.005", "KWD"), Ok(1005));
+        assert_eq!(minor_units("100", "JPY"), Ok(100));
+        assert_eq!(minor_units("100", "USD"), Ok(10000));
+    }
+
+    #[test]
+    fn more_decimals_than_the_currency_has_are_rejected() {
+        let too_many = |currency_code: &str, exponent| {
+            Err(MoneyError::TooManyDecimals {
+                currency_code: currency_code.to_string(),
+                exponent,
+            })
+        };
+        assert_eq!(minor_units("1.005", "USD"), too_many("USD", 2));
+        assert_eq!(minor_units("100.0", "JPY"), too_many("JPY", 0));
+    }
+
+    #[test]
+    fn sign_is_kept() {
+        assert_eq!(minor_units("-0.01", "USD"), Ok(-1));
+        assert_eq!(minor_units("-5", "JPY"), Ok(-5));
+    }
+
+    #[test]
+    fn malformed_amounts_are_rejected() {
+        for amount in [
+            "", "1.", ".5", "-", "--1", "+1", "1.2.3", "1,00", "1e3", " 1",
+        ] {
+            assert_eq!(
+                minor_units(amount, "USD"),
+                Err(MoneyError::InvalidAmount(amount.to_string())),
+                "{:?}",
+                amount
+            );
+        }
+    }
+
+    #[test]
+    fn amounts_beyond_i64_overflow() {
+        assert_eq!(minor_units("9223372036854775807", "JPY"), Ok(i64::MAX));
+        assert_eq!(
+            minor_units("9223372036854775808", "JPY"),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            minor_units("92233720368547758.08", "USD"),
+            Err(MoneyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn unknown_currencies_are_rejected() {
+        assert_eq!(
+            minor_units("1", "XYZ"),
+            Err(MoneyError::UnknownCurrency("XYZ".to_string()))
+        );
+    }
+
+    #[test]
+    fn display_uses_the_currency_exponent() {
+        let money =
+            |amount, currency_code| Money::parse(amount, currency_code).unwrap().to_string();
+        assert_eq!(money("100.5", "USD"), "100.50 USD");
+        assert_eq!(money("-0.01", "USD"), "-0.01 USD");
+        assert_eq!(money("100", "JPY"), "100 JPY");
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use serde::Deserialize;
+use tonic::body::BoxBody;
+use tonic::codegen::{BoxFuture, Service, http};
+use tonic::service::interceptor::InterceptedService;
+use tonic::transport::NamedService;
+use tonic::{Request, Status};
+
+use crate::auth::{Authenticator, Caller};
+
+/// Whose data a role may touch through a method.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Only requests made on behalf of the caller's own user_id.
+    Own,
+    /// Requests made on behalf of any user.
+    Any,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    roles: HashMap<String, HashMap<String, Scope>>,
+}
+
+/// Maps each role to the RPC methods it may call and the scope it may call them with.
+///
+/// Methods are full gRPC paths such as `/services.PaymentService/ProcessPayment`;
+/// `/services.PaymentService/*` matches every method of a service and `*` matches all.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    roles: Arc<HashMap<String, HashMap<String, Scope>>>,
+}
+
+impl Policy {
+    /// Reads the role policy from a TOML file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to read {}: {}", path.display(), e),
+            )
+        })?;
+        let file: PolicyFile =
+            toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Policy {
+            roles: Arc::new(file.roles),
+        })
+    }
+
+    /// The widest scope any of `roles` grants for `method`, or `None` if none allows it.
+    pub fn scope(&self, roles: &[String], method: &str) -> Option<Scope> {
+        roles
+            .iter()
+            .filter_map(|role| self.roles.get(role))
+            .flat_map(|methods| methods.iter())
+            .filter(|(pattern, _)| matches(pattern, method))
+            .map(|(_, scope)| *scope)
+            .max()
+    }
+}
+
+fn matches(pattern: &str, method: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => method.starts_with(prefix),
+        None => pattern == method,
+    }
+}
+
+/// What the policy allowed the caller to do for the current request.
+#[derive(Debug, Clone)]
+pub struct Access {
+    pub caller: Caller,
+    pub scope: Scope,
+}
+
+impl Access {
+    /// Rejects requests made on behalf of another user unless the scope allows any user.
+    pub fn check_user(&self, user_id: &str) -> Result<(), Status> {
+        if self.scope == Scope::Any || self.caller.user_id == user_id {
+            Ok(())
+        } else {
+            Err(Status::permission_denied(format!(
+                "{} may not act as {}",
+                self.caller.user_id, user_id
+            )))
+        }
+    }
+}
+
+/// Returns the access `Authorize` granted to `request`.
+pub fn access<T>(request: &Request<T>) -> Result<Access, Status> {
+    request
+        .extensions()
+        .get::<Access>()
+        .cloned()
+        .ok_or_else(|| Status::permission_denied("request was not authorized"))
+}
+
+/// Wraps a generated service so the policy is checked against the authenticated caller
+/// before any handler runs. Allowed requests carry an `Access` in their extensions.
+#[derive(Debug, Clone)]
+pub struct Authorize<S> {
+    inner: S,
+    policy: Policy,
+}
+
+impl<S> Authorize<S> {
+    pub fn new(inner: S, policy: Policy) -> Self {
+        Authorize { inner, policy }
+    }
+
+    fn decide<B>(&self, request: &http::Request<B>) -> Result<Access, Status> {
+        let method = request.uri().path();
+        let caller = request
+            .extensions()
+            .get::<Caller>()
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("request was not authenticated"))?;
+        let scope = self.policy.scope(&caller.roles, method).ok_or_else(|| {
+            Status::permission_denied(format!("{} may not call {}", caller.user_id, method))
+        })?;
+        Ok(Access { caller, scope })
+    }
+}
+
+impl<S, B> Service<http::Request<B>> for Authorize<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<B>) -> Self::Future {
+        match self.decide(&request) {
+            Ok(access) => {
+                tracing::Span::current().record("caller", access.caller.user_id.as_str());
+                request.extensions_mut().insert(access);
+                Box::pin(self.inner.call(request))
+            }
+            Err(status) => Box::pin(async move { Ok(status.to_http()) }),
+        }
+    }
+}
+
+impl<S: NamedService> NamedService for Authorize<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+/// Authenticates requests to `service` and then authorizes them against `policy`.
+pub fn protect<S>(
+    service: S,
+    authenticator: &Authenticator,
+    policy: &Policy,
+) -> InterceptedService<Authorize<S>, Authenticator> {
+    InterceptedService::new(Authorize::new(service, policy.clone()), authenticator.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAY: &str = "/services.PaymentService/ProcessPayment";
+    const HISTORY: &str = "/services.TransactionService/GetTransactionHistory";
+
+    fn policy(toml: &str) -> Policy {
+        let file: PolicyFile = toml::from_str(toml).unwrap();
+        Policy {
+            roles: Arc::new(file.roles),
+        }
+    }
+
+    fn roles(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn patterns_match_exact_methods_services_and_everything() {
+        assert!(matches(PAY, PAY));
+        assert!(!matches(PAY, HISTORY));
+        assert!(matches("/services.PaymentService/*", PAY));
+        assert!(!matches("/services.PaymentService/*", HISTORY));
+        assert!(matches("*", PAY));
+        assert!(matches("*", HISTORY));
+    }
+
+    #[test]
+    fn widest_scope_across_roles_wins() {
+        let policy = policy(
+            r#"
+            [roles.user]
+            "/services.PaymentService/*" = "own"
+            [roles.auditor]
+            "/services.PaymentService/ProcessPayment" = "any"
+            "#,
+        );
+        assert_eq!(policy.scope(&roles(&["user"]), PAY), Some(Scope::Own));
+        assert_eq!(
+            policy.scope(&roles(&["user", "auditor"]), PAY),
+            Some(Scope::Any)
+        );
+        assert_eq!(policy.scope(&roles(&["user", "auditor"]), HISTORY), None);
+    }
+
+    #[test]
+    fn wildcard_role_allows_every_method() {
+        let policy = policy(
+            r#"
+            [roles.admin]
+            "*" = "any"
+            "#,
+        );
+        assert_eq!(policy.scope(&roles(&["admin"]), HISTORY), Some(Scope::Any));
+    }
+
+    #[test]
+    fn unknown_roles_are_denied() {
+        let policy = policy(
+            r#"
+            [roles.user]
+            "*" = "own"
+            "#,
+        );
+        assert_eq!(policy.scope(&roles(&["stranger"]), PAY), None);
+        assert_eq!(policy.scope(&[], PAY), None);
+    }
+}
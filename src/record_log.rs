@@ -0,0 +1,182 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use prost::Message;
+
+/// Append-only file of length-delimited protobuf records.
+pub struct RecordLog {
+    file: File,
+}
+
+impl RecordLog {
+    /// Opens the log at `path`, creating it if needed, and decodes every record in it.
+    ///
+    /// A record cut short at the end of the file, as left by a crash mid-write, is
+    /// truncated away. An undecodable record anywhere else is an error.
+    pub fn open<T: Message + Default>(path: impl AsRef<Path>) -> io::Result<(Self, Vec<T>)> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while offset < contents.len() {
+            let record = next_record(&contents[offset..])
+                .and_then(|(body, len)| Some((T::decode(body).ok()?, len)));
+            match record {
+                Some((record, len)) => {
+                    records.push(record);
+                    offset += len;
+                }
+                None if is_tail(&contents[offset..]) => {
+                    tracing::warn!(
+                        offset,
+                        path = %path.display(),
+                        "truncating partial record"
+                    );
+                    file.set_len(offset as u64)?;
+                    file.sync_data()?;
+                    break;
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("corrupt record at byte {} of {}", offset, path.display()),
+                    ));
+                }
+            }
+        }
+
+        Ok((RecordLog { file }, records))
+    }
+
+    /// Appends `record` and waits for it to reach the disk.
+    pub fn append<T: Message>(&mut self, record: &T) -> io::Result<()> {
+        self.file
+            .write_all(&record.encode_length_delimited_to_vec())?;
+        self.file.sync_data()
+    }
+}
+
+/// Splits the record at the start of `buf` into its body and its length including the
+/// length prefix, or returns `None` if `buf` ends before the record does.
+fn next_record(buf: &[u8]) -> Option<(&[u8], usize)> {
+    let mut rest = buf;
+    let len = usize::try_from(prost::encoding::decode_varint(&mut rest).ok()?).ok()?;
+    let prefix = buf.len() - rest.len();
+    (len <= rest.len()).then(|| (&rest[..len], prefix + len))
+}
+
+/// Whether a bad record at the start of `buf` is the last thing in the file, so it can
+/// only be a write that never finished.
+fn is_tail(buf: &[u8]) -> bool {
+    match next_record(buf) {
+        Some((_, len)) => len == buf.len(),
+        // A length prefix is at most ten bytes, so a longer remainder with no valid
+        // prefix is corruption rather than a prefix cut short.
+        None => {
+            let mut rest = buf;
+            prost::encoding::decode_varint(&mut rest).is_ok() || buf.len() < 10
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    #[derive(Clone, PartialEq, Message)]
+    struct Record {
+        #[prost(string, tag = "1")]
+        text: String,
+    }
+
+    fn record(text: &str) -> Record {
+        Record {
+            text: text.to_string(),
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("record_log_{}_{}.log", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn write_records(path: &Path, texts: &[&str]) {
+        let (mut log, _) = RecordLog::open::<Record>(path).unwrap();
+        for text in texts {
+            log.append(&record(text)).unwrap();
+        }
+    }
+
+    fn append_bytes(path: &Path, bytes: &[u8]) {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn reopening_returns_every_record() {
+        let path = temp_path("reopen");
+        write_records(&path, &["a", "b"]);
+        let (_, records) = RecordLog::open::<Record>(&path).unwrap();
+        assert_eq!(records, [record("a"), record("b")]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn partial_trailing_record_is_truncated() {
+        let path = temp_path("torn");
+        write_records(&path, &["a", "b"]);
+        let good_len = std::fs::metadata(&path).unwrap().len();
+        let torn = record("cut short").encode_length_delimited_to_vec();
+        append_bytes(&path, &torn[..torn.len() - 3]);
+
+        let (mut log, records) = RecordLog::open::<Record>(&path).unwrap();
+        assert_eq!(records, [record("a"), record("b")]);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), good_len);
+
+        log.append(&record("c")).unwrap();
+        drop(log);
+        let (_, records) = RecordLog::open::<Record>(&path).unwrap();
+        assert_eq!(records, [record("a"), record("b"), record("c")]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn partial_length_prefix_is_truncated() {
+        let path = temp_path("torn_prefix");
+        write_records(&path, &["a"]);
+        append_bytes(&path, &[0x80]);
+
+        let (_, records) = RecordLog::open::<Record>(&path).unwrap();
+        assert_eq!(records, [record("a")]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corruption_before_the_last_record_is_an_error() {
+        let path = temp_path("corrupt");
+        // A complete record whose body is not a valid `Record`, followed by a good one.
+        append_bytes(&path, &[2, 0xff, 0xff]);
+        append_bytes(&path, &record("a").encode_length_delimited_to_vec());
+
+        let err = RecordLog::open::<Record>(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Code, Request, Response, Status, Streaming};
+
+use grpc_tutorial::grpc::reflection::v1alpha::server_reflection_request::MessageRequest;
+use grpc_tutorial::grpc::reflection::v1alpha::server_reflection_response::MessageResponse;
+use grpc_tutorial::grpc::reflection::v1alpha::server_reflection_server::ServerReflection;
+use grpc_tutorial::grpc::reflection::v1alpha::{
+    ErrorResponse, ExtensionNumberResponse, FileDescriptorResponse, ListServiceResponse,
+    ServerReflectionRequest, ServerReflectionResponse, ServiceResponse,
+};
+
+use crate::shutdown::{self, Shutdown};
+
+/// Proto files by name, and which file declares each fully-qualified symbol.
+struct Descriptors {
+    files: HashMap<String, FileDescriptorProto>,
+    symbols: HashMap<String, String>,
+    services: Vec<String>,
+}
+
+impl Descriptors {
+    fn new(descriptor_set: &[u8], services: Vec<String>) -> Result<Self, prost::DecodeError> {
+        let mut files = HashMap::new();
+        let mut symbols = HashMap::new();
+        for file in FileDescriptorSet::decode(descriptor_set)?.file {
+            let prefix = match file.package() {
+                "" => String::new(),
+                package => format!("{}.", package),
+            };
+            let mut declare = |symbol: String| {
+                symbols.insert(symbol, file.name().to_string());
+            };
+            for message in &file.message_type {
+                declare_message(&mut declare, &prefix, message);
+            }
+            for enum_type in &file.enum_type {
+                declare(format!("{}{}", prefix, enum_type.name()));
+            }
+            for service in &file.service {
+                let service_name = format!("{}{}", prefix, service.name());
+                for method in &service.method {
+                    declare(format!("{}.{}", service_name, method.name()));
+                }
+                declare(service_name);
+            }
+            files.insert(file.name().to_string(), file);
+        }
+        Ok(Descriptors {
+            files,
+            symbols,
+            services,
+        })
+    }
+
+    fn respond(&self, request: &ServerReflectionRequest) -> MessageResponse {
+        match &request.message_request {
+            Some(MessageRequest::FileByFilename(name)) => self.file_with_dependencies(name),
+            Some(MessageRequest::FileContainingSymbol(symbol)) => match self.symbols.get(symbol) {
+                Some(name) => self.file_with_dependencies(name),
+                None => error(Code::NotFound, format!("unknown symbol {}", symbol)),
+            },
+            // None of our proto files declare extensions.
+            Some(MessageRequest::FileContainingExtension(extension)) => error(
+                Code::NotFound,
+                format!("{} has no extensions", extension.containing_type),
+            ),
+            Some(MessageRequest::AllExtensionNumbersOfType(type_name)) => {
+                if self.symbols.contains_key(type_name) {
+                    MessageResponse::AllExtensionNumbersResponse(ExtensionNumberResponse {
+                        base_type_name: type_name.clone(),
+                        extension_number: Vec::new(),
+                    })
+                } else {
+                    error(Code::NotFound, format!("unknown type {}", type_name))
+                }
+            }
+            Some(MessageRequest::ListServices(_)) => {
+                MessageResponse::ListServicesResponse(ListServiceResponse {
+                    service: self
+                        .services
+                        .iter()
+                        .map(|name| ServiceResponse { name: name.clone() })
+                        .collect(),
+                })
+            }
+            None => error(Code::InvalidArgument, "message_request is required"),
+        }
+    }
+
+    /// Encodes the file `name` followed by every file it imports, directly or not.
+    fn file_with_dependencies(&self, name: &str) -> MessageResponse {
+        if !self.files.contains_key(name) {
+            return error(Code::NotFound, format!("unknown file {}", name));
+        }
+
+        let mut encoded = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![name];
+        while let Some(name) = pending.pop() {
+            let Some(file) = self.files.get(name) else {
+                continue;
+            };
+            if !seen.insert(name) {
+                continue;
+            }
+            encoded.push(file.encode_to_vec());
+            pending.extend(file.dependency.iter().map(String::as_str));
+        }
+        MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+            file_descriptor_proto: encoded,
+        })
+    }
+}
+
+fn declare_message(declare: &mut impl FnMut(String), prefix: &str, message: &DescriptorProto) {
+    let name = format!("{}{}", prefix, message.name());
+    let nested_prefix = format!("{}.", name);
+    for nested in &message.nested_type {
+        declare_message(declare, &nested_prefix, nested);
+    }
+    for enum_type in &message.enum_type {
+        declare(format!("{}{}", nested_prefix, enum_type.name()));
+    }
+    declare(name);
+}
+
+fn error(code: Code, message: impl Into<String>) -> MessageResponse {
+    MessageResponse::ErrorResponse(ErrorResponse {
+        error_code: code as i32,
+        error_message: message.into(),
+    })
+}
+
+/// Answers `grpc.reflection.v1alpha.ServerReflection` requests from a descriptor set, so
+/// generic clients can call the server without a copy of its proto files.
+pub struct ReflectionService {
+    descriptors: Arc<Descriptors>,
+    shutdown: Shutdown,
+}
+
+impl ReflectionService {
+    /// Describes the files in `descriptor_set` and lists `services` as the ones served.
+    pub fn new(
+        descriptor_set: &[u8],
+        services: Vec<String>,
+        shutdown: Shutdown,
+    ) -> Result<Self, prost::DecodeError> {
+        Ok(ReflectionService {
+            descriptors: Arc::new(Descriptors::new(descriptor_set, services)?),
+            shutdown,
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl ServerReflection for ReflectionService {
+    type ServerReflectionInfoStream = ReceiverStream<Result<ServerReflectionResponse, Status>>;
+
+    async fn server_reflection_info(
+        &self,
+        request: Request<Streaming<ServerReflectionRequest>>,
+    ) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+        let mut stream = request.into_inner();
+        let descriptors = self.descriptors.clone();
+        let mut shutdown = self.shutdown.clone();
+        let (tx, rx) = mpsc::channel(1);
+
+        tokio::spawn(async move {
+            loop {
+                let request = tokio::select! {
+                    next = stream.message() => match next {
+                        Ok(Some(request)) => request,
+                        Ok(None) | Err(_) => return,
+                    },
+                    _ = shutdown.wait() => {
+                        shutdown::notify(&tx, Err(Status::unavailable(shutdown::REASON))).await;
+                        return;
+                    }
+                };
+                let response = ServerReflectionResponse {
+                    valid_host: request.host.clone(),
+                    message_response: Some(descriptors.respond(&request)),
+                    original_request: Some(request),
+                };
+                if tx.send(Ok(response)).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptors() -> Descriptors {
+        Descriptors::new(
+            grpc_tutorial::FILE_DESCRIPTOR_SET,
+            vec!["services.PaymentService".to_string()],
+        )
+        .unwrap()
+    }
+
+    fn file_names(response: MessageResponse) -> Vec<String> {
+        let MessageResponse::FileDescriptorResponse(files) = response else {
+            panic!("expected files, got {:?}", response);
+        };
+        files
+            .file_descriptor_proto
+            .iter()
+            .map(|file| {
+                FileDescriptorProto::decode(file.as_slice())
+                    .unwrap()
+                    .name()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    fn request(message_request: MessageRequest) -> ServerReflectionRequest {
+        ServerReflectionRequest {
+            host: String::new(),
+            message_request: Some(message_request),
+        }
+    }
+
+    #[test]
+    fn symbols_resolve_to_their_file_and_its_imports() {
+        let descriptors = descriptors();
+        for symbol in [
+            "services.PaymentService",
+            "services.PaymentService.ProcessPayment",
+            "services.ChatMessage",
+            "services.PaymentStatus",
+        ] {
+            let response = descriptors.respond(&request(MessageRequest::FileContainingSymbol(
+                symbol.to_string(),
+            )));
+            let names = file_names(response);
+            assert_eq!(names[0], "services.proto", "{}", symbol);
+            assert!(names.contains(&"google/protobuf/timestamp.proto".to_string()));
+        }
+    }
+
+    #[test]
+    fn nested_types_are_declared() {
+        let descriptors = descriptors();
+        let response = descriptors.respond(&request(MessageRequest::FileContainingSymbol(
+            "grpc.health.v1.HealthCheckResponse.ServingStatus".to_string(),
+        )));
+        assert_eq!(file_names(response), ["grpc/health/v1/health.proto"]);
+    }
+
+    #[test]
+    fn unknown_symbols_are_not_found() {
+        let response = descriptors().respond(&request(MessageRequest::FileContainingSymbol(
+            "services.Nope".to_string(),
+        )));
+        let MessageResponse::ErrorResponse(error) = response else {
+            panic!("expected an error, got {:?}", response);
+        };
+        assert_eq!(error.error_code, Code::NotFound as i32);
+    }
+
+    #[test]
+    fn listed_services_are_the_served_ones() {
+        let response = descriptors().respond(&request(MessageRequest::ListServices(String::new())));
+        let MessageResponse::ListServicesResponse(list) = response else {
+            panic!("expected services, got {:?}", response);
+        };
+        assert_eq!(
+            list.service,
+            [ServiceResponse {
+                name: "services.PaymentService".to_string()
+            }]
+        );
+    }
+}
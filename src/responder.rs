@@ -0,0 +1,147 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::handoff::HandoffQueue;
+
+/// Sender of the bot's replies, so clients can tell them apart from the customer's own
+/// messages.
+pub const BOT_USER_ID: &str = "cs_bot";
+
+/// Decides what the customer service bot answers to a chat message.
+#[tonic::async_trait]
+pub trait ChatResponder: Send + Sync {
+    /// Returns the reply to `text`, sent by `user_id` in `room`, or `None` to stay quiet.
+    async fn respond(&self, room: &str, user_id: &str, text: &str) -> Option<String>;
+}
+
+/// Tells every customer that their message will be answered during office hours.
+pub struct CannedResponder;
+
+#[tonic::async_trait]
+impl ChatResponder for CannedResponder {
+    async fn respond(&self, _room: &str, _user_id: &str, text: &str) -> Option<String> {
+        Some(format!(
+            "Terima kasih telah melakukan chat kepada CS virtual, Pesan anda akan dibalas pada jam kerja. pesan: {}",
+            text
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeywordRule {
+    /// Matched case-insensitively anywhere in the message.
+    pub keywords: Vec<String>,
+    pub reply: String,
+}
+
+/// Answers with the first rule whose keywords appear in the message, and defers to
+/// `fallback` otherwise.
+pub struct KeywordResponder {
+    rules: Vec<KeywordRule>,
+    fallback: Box<dyn ChatResponder>,
+}
+
+impl KeywordResponder {
+    pub fn new(rules: Vec<KeywordRule>, fallback: Box<dyn ChatResponder>) -> Self {
+        let rules = rules
+            .into_iter()
+            .map(|rule| KeywordRule {
+                keywords: rule.keywords.iter().map(|k| k.to_lowercase()).collect(),
+                reply: rule.reply,
+            })
+            .collect();
+        KeywordResponder { rules, fallback }
+    }
+}
+
+#[tonic::async_trait]
+impl ChatResponder for KeywordResponder {
+    async fn respond(&self, room: &str, user_id: &str, text: &str) -> Option<String> {
+        let text_lower = text.to_lowercase();
+        let rule = self.rules.iter().find(|rule| {
+            rule.keywords
+                .iter()
+                .any(|keyword| text_lower.contains(keyword.as_str()))
+        });
+
+        match rule {
+            Some(rule) => Some(rule.reply.clone()),
+            None => self.fallback.respond(room, user_id, text).await,
+        }
+    }
+}
+
+/// Hands every conversation over to a human agent.
+pub struct HandoffResponder {
+    queue: Arc<HandoffQueue>,
+}
+
+impl HandoffResponder {
+    pub fn new(queue: Arc<HandoffQueue>) -> Self {
+        HandoffResponder { queue }
+    }
+}
+
+#[tonic::async_trait]
+impl ChatResponder for HandoffResponder {
+    async fn respond(&self, room: &str, user_id: &str, text: &str) -> Option<String> {
+        self.queue
+            .enqueue(room, user_id, text)
+            .then(|| "Mohon tunggu, pesan anda akan diteruskan ke agen CS kami.".to_string())
+    }
+}
+
+/// What answers messages that no keyword rule matches.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fallback {
+    #[default]
+    Canned,
+    Handoff,
+}
+
+/// Contents of the responder config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ResponderConfig {
+    #[serde(default)]
+    pub fallback: Fallback,
+    #[serde(default)]
+    pub rules: Vec<KeywordRule>,
+}
+
+impl ResponderConfig {
+    /// Reads the config at `path`; a missing file gives the default canned responder.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ResponderConfig::default()),
+            Err(e) => Err(io::Error::new(
+                e.kind(),
+                format!("failed to read {}: {}", path.display(), e),
+            )),
+        }
+    }
+
+    pub fn build(self, queue: Arc<HandoffQueue>) -> Box<dyn ChatResponder> {
+        let fallback: Box<dyn ChatResponder> = match self.fallback {
+            Fallback::Canned => Box::new(CannedResponder),
+            Fallback::Handoff => Box::new(HandoffResponder::new(queue)),
+        };
+        if self.rules.is_empty() {
+            fallback
+        } else {
+            Box::new(KeywordResponder::new(self.rules, fallback))
+        }
+    }
+}
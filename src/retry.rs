@@ -0,0 +1,167 @@
+use std::future::Future;
+use std::time::Duration;
+
+use tonic::{Code, Status};
+
+/// How a failing call is retried: after a delay that starts at `initial` and doubles
+/// with each retry up to `max`, for at most `attempts` tries in all.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(5),
+            attempts: 5,
+        }
+    }
+}
+
+impl Backoff {
+    /// The delay before retry `retry`, counting from zero.
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max)
+    }
+
+    /// Makes `call` until it succeeds, fails with a status that is not transient, or has
+    /// been tried `attempts` times, and returns its last result.
+    pub async fn retry<T, F, Fut>(&self, mut call: F) -> Result<T, Status>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Status>>,
+    {
+        let mut retries = Retries::new(*self);
+        loop {
+            match call().await {
+                Err(status) if is_transient(&status) && retries.wait().await => {}
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Consecutive failures of a call retried by hand, such as a stream that is resumed
+/// each time it breaks.
+#[derive(Debug)]
+pub struct Retries {
+    backoff: Backoff,
+    failures: u32,
+}
+
+impl Retries {
+    pub fn new(backoff: Backoff) -> Self {
+        Retries {
+            backoff,
+            failures: 0,
+        }
+    }
+
+    /// Records that the call got somewhere, so the next failure starts the backoff over.
+    pub fn reset(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Records a failure and waits out the backoff before the next attempt. Returns
+    /// false without waiting once every attempt has been used.
+    pub async fn wait(&mut self) -> bool {
+        self.failures += 1;
+        if self.failures >= self.backoff.attempts {
+            return false;
+        }
+        tokio::time::sleep(self.backoff.delay(self.failures - 1)).await;
+        true
+    }
+}
+
+/// Whether a call that failed with `status` might succeed if made again, because the
+/// server could not be reached or the connection broke. Statuses the server sends carry
+/// no source error, so an `UNKNOWN` one is the server's answer rather than a lost
+/// connection.
+pub fn is_transient(status: &Status) -> bool {
+    match status.code() {
+        Code::Unavailable => true,
+        Code::Unknown => std::error::Error::source(status).is_some(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::Cell;
+
+    fn backoff(attempts: u32) -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(1),
+            max: Duration::from_millis(4),
+            attempts,
+        }
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_maximum() {
+        let backoff = backoff(10);
+        let delays: Vec<_> = (0..5)
+            .map(|retry| backoff.delay(retry).as_millis())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 4, 4]);
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_millis(4));
+    }
+
+    #[test]
+    fn only_lost_connections_are_transient() {
+        assert!(is_transient(&Status::unavailable("server shutting down")));
+        assert!(!is_transient(&Status::unknown("sent by the server")));
+        assert!(!is_transient(&Status::invalid_argument("bad amount")));
+    }
+
+    #[tokio::test]
+    async fn retries_until_attempts_run_out() {
+        let calls = Cell::new(0);
+        let result: Result<(), Status> = backoff(3)
+            .retry(|| async {
+                calls.set(calls.get() + 1);
+                Err(Status::unavailable("down"))
+            })
+            .await;
+        assert_eq!(result.unwrap_err().code(), Code::Unavailable);
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_are_not_retried() {
+        let calls = Cell::new(0);
+        let result: Result<(), Status> = backoff(3)
+            .retry(|| async {
+                calls.set(calls.get() + 1);
+                Err(Status::permission_denied("no"))
+            })
+            .await;
+        assert_eq!(result.unwrap_err().code(), Code::PermissionDenied);
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn success_after_a_failure_is_returned() {
+        let calls = Cell::new(0);
+        let result = backoff(3)
+            .retry(|| async {
+                calls.set(calls.get() + 1);
+                if calls.get() < 2 {
+                    Err(Status::unavailable("down"))
+                } else {
+                    Ok(calls.get())
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+    }
+}
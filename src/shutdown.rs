@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use tokio::sync::{mpsc, watch};
+
+/// Reason given to streams the server ends because it is shutting down.
+pub const REASON: &str = "server shutting down";
+
+/// How long a full stream gets to make room for the messages that end it.
+const NOTICE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Lets long-lived streams notice that the server has started shutting down, so they
+/// can end instead of holding their connection open past the drain deadline.
+#[derive(Debug, Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Returns a handle to clone into services along with the sender that starts the
+    /// shutdown.
+    pub fn new() -> (watch::Sender<bool>, Self) {
+        let (tx, rx) = watch::channel(false);
+        (tx, Shutdown(rx))
+    }
+
+    /// Resolves once the shutdown has started.
+    pub async fn wait(&mut self) {
+        // The sender only goes away once the server has stopped, which counts too.
+        let _ = self.0.wait_for(|started| *started).await;
+    }
+}
+
+/// Queues a message that ends a stream, waiting briefly if the stream's buffer is full
+/// rather than dropping it. Gives up after `NOTICE_TIMEOUT` so a stalled client cannot
+/// hold up the shutdown.
+pub async fn notify<T>(tx: &mpsc::Sender<T>, message: T) {
+    let _ = tokio::time::timeout(NOTICE_TIMEOUT, tx.send(message)).await;
+}
+
+/// Resolves when the process receives SIGINT or, on Unix, SIGTERM.
+pub async fn signal() {
+    let interrupt = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "failed to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
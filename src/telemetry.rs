@@ -0,0 +1,186 @@
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+use std::time::Instant;
+
+use tonic::body::BoxBody;
+use tonic::codegen::{Body, BoxFuture, Service, http};
+use tonic::{Code, Status};
+use tower_layer::Layer;
+use tracing::Instrument;
+use tracing::field::Empty;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::LogFormat;
+use crate::metrics::Metrics;
+
+/// Header a caller can set to correlate its requests with the server's logs. Requests
+/// without one get a fresh id, and either way it is echoed on the response.
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Sends log events to stdout in `format`, at the levels `RUST_LOG` asks for and
+/// `info` otherwise.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Pretty => builder.pretty().init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Wraps every call in an `rpc` span carrying its method, caller, request id, status
+/// code and latency, and counts it in `metrics` once it ends. The caller is filled in
+/// by `policy::Authorize` once known.
+#[derive(Clone)]
+pub struct TraceLayer {
+    metrics: Metrics,
+}
+
+impl TraceLayer {
+    pub fn new(metrics: Metrics) -> Self {
+        TraceLayer { metrics }
+    }
+}
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = Trace<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Trace {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Trace<S> {
+    inner: S,
+    metrics: Metrics,
+}
+
+impl<S, B> Service<http::Request<B>> for Trace<S>
+where
+    S: Service<http::Request<B>, Response = http::Response<BoxBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = http::Response<TracedBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .map(str::to_string)
+            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let span = tracing::info_span!(
+            "rpc",
+            method = request.uri().path(),
+            caller = Empty,
+            request_id = request_id.as_str(),
+            code = Empty,
+            latency_ms = Empty,
+        );
+        let call = Call {
+            span: span.clone(),
+            method: request.uri().path().to_string(),
+            metrics: self.metrics.clone(),
+            started: Instant::now(),
+            code: None,
+        };
+
+        // Entered while the inner services decide, so they can record the caller.
+        let future = span.in_scope(|| self.inner.call(request));
+        Box::pin(
+            async move {
+                let mut response = future.await?;
+                if let Ok(id) = http::HeaderValue::from_str(&request_id) {
+                    response.headers_mut().insert(REQUEST_ID_HEADER, id);
+                }
+                // Errors returned before any message are sent in the headers alone.
+                let code = Status::from_header_map(response.headers()).map(|status| status.code());
+                Ok(response.map(|inner| TracedBody {
+                    inner,
+                    call: Some(Call { code, ..call }),
+                }))
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// An RPC whose outcome has not been logged or counted yet.
+struct Call {
+    span: tracing::Span,
+    method: String,
+    metrics: Metrics,
+    started: Instant,
+    /// Status from the response headers, for responses that carry no trailers.
+    code: Option<Code>,
+}
+
+impl Call {
+    fn finish(self, code: Code) {
+        let latency = self.started.elapsed();
+        self.metrics.rpc_finished(&self.method, code, latency);
+        self.span.record("code", tracing::field::debug(code));
+        self.span
+            .record("latency_ms", latency.as_secs_f64() * 1000.0);
+        tracing::info!(parent: &self.span, "finished");
+    }
+}
+
+/// Response body that logs the call once its status is known: from the trailers, from
+/// the headers of an error-only response, or as `CANCELLED` if the stream is dropped
+/// before it ends.
+pub struct TracedBody {
+    inner: BoxBody,
+    call: Option<Call>,
+}
+
+impl Body for TracedBody {
+    type Data = tonic::codegen::Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<http::HeaderMap>, Self::Error>> {
+        let trailers = ready!(Pin::new(&mut self.inner).poll_trailers(cx));
+        if let Some(call) = self.call.take() {
+            let code = match &trailers {
+                Ok(Some(trailers)) => Status::from_header_map(trailers).map(|status| status.code()),
+                Ok(None) => call.code,
+                Err(status) => Some(status.code()),
+            };
+            call.finish(code.unwrap_or(Code::Ok));
+        }
+        Poll::Ready(trailers)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+impl Drop for TracedBody {
+    fn drop(&mut self) {
+        if let Some(call) = self.call.take() {
+            let code = call.code.unwrap_or(Code::Cancelled);
+            call.finish(code);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+use prost_types::Timestamp;
+
+/// Seconds of 0001-01-01T00:00:00Z, the earliest time a `Timestamp` may hold.
+const MIN_SECONDS: i64 = -62_135_596_800;
+/// Seconds of 9999-12-31T23:59:59Z, the latest time a `Timestamp` may hold.
+const MAX_SECONDS: i64 = 253_402_300_799;
+
+pub fn from_millis(millis: i64) -> Timestamp {
+    Timestamp {
+        seconds: millis.div_euclid(1000),
+        nanos: (millis.rem_euclid(1000) * 1_000_000) as i32,
+    }
+}
+
+pub fn to_millis(timestamp: &Timestamp) -> i64 {
+    timestamp
+        .seconds
+        .saturating_mul(1000)
+        .saturating_add(i64::from(timestamp.nanos) / 1_000_000)
+}
+
+/// Whether `timestamp` lies in years 0001 to 9999 with nanos in `0..1_000_000_000`, as
+/// the `google.protobuf.Timestamp` spec requires.
+pub fn is_valid(timestamp: &Timestamp) -> bool {
+    (MIN_SECONDS..=MAX_SECONDS).contains(&timestamp.seconds)
+        && (0..1_000_000_000).contains(&timestamp.nanos)
+}
+
+pub fn now() -> Timestamp {
+    from_millis(Utc::now().timestamp_millis())
+}
+
+/// Converts a wire timestamp to a `DateTime`, or `None` if it is out of range.
+pub fn to_datetime(timestamp: &Timestamp) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(timestamp.seconds, u32::try_from(timestamp.nanos).ok()?)
+}
@@ -0,0 +1,48 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use tonic::transport::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    fs::read(path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to read {}: {}", path.display(), e),
+        )
+    })
+}
+
+/// Builds server TLS settings from PEM files. With `client_ca`, every client must present
+/// a certificate signed by that CA (mutual TLS).
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> io::Result<ServerTlsConfig> {
+    let mut config = ServerTlsConfig::new().identity(Identity::from_pem(read(cert)?, read(key)?));
+    if let Some(client_ca) = client_ca {
+        config = config.client_ca_root(Certificate::from_pem(read(client_ca)?));
+    }
+    Ok(config)
+}
+
+/// Builds client TLS settings from PEM files.
+///
+/// `ca` replaces the system roots for verifying the server, which is how a self-signed
+/// setup is trusted. `identity` is the client certificate and key for mutual TLS.
+/// `domain` is the name the server certificate must be valid for.
+pub fn client_config(
+    ca: Option<&Path>,
+    identity: Option<(&Path, &Path)>,
+    domain: &str,
+) -> io::Result<ClientTlsConfig> {
+    let mut config = ClientTlsConfig::new().domain_name(domain);
+    if let Some(ca) = ca {
+        config = config.ca_certificate(Certificate::from_pem(read(ca)?));
+    }
+    if let Some((cert, key)) = identity {
+        config = config.identity(Identity::from_pem(read(cert)?, read(key)?));
+    }
+    Ok(config)
+}
@@ -0,0 +1,370 @@
+use prost::Message;
+use prost_types::Timestamp;
+use tonic::{Code, Status};
+
+use grpc_tutorial::google::rpc::{self, BadRequest, bad_request::FieldViolation};
+use grpc_tutorial::services::{PaymentRequest, PaymentStatus, TransactionRequest};
+use grpc_tutorial::{money, timestamp};
+
+use crate::ledger::HistoryQuery;
+
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+const MAX_PAGE_SIZE: u32 = 1000;
+const BAD_REQUEST_TYPE_URL: &str = "type.googleapis.com/google.rpc.BadRequest";
+
+/// Field-level problems found while checking a request.
+#[derive(Default)]
+pub struct Violations(Vec<FieldViolation>);
+
+impl Violations {
+    pub fn add(&mut self, field: &str, description: impl Into<String>) {
+        self.0.push(FieldViolation {
+            field: field.to_string(),
+            description: description.into(),
+        });
+    }
+
+    /// Returns `INVALID_ARGUMENT` if any violation was recorded. The details carry a
+    /// `google.rpc.Status` wrapping a `google.rpc.BadRequest`, as standard clients expect.
+    pub fn into_result(self) -> Result<(), Status> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+
+        let message = self
+            .0
+            .iter()
+            .map(|v| format!("{}: {}", v.field, v.description))
+            .collect::<Vec<_>>()
+            .join("; ");
+        let bad_request = BadRequest {
+            field_violations: self.0,
+        };
+        let details = rpc::Status {
+            code: Code::InvalidArgument as i32,
+            message: message.clone(),
+            details: vec![prost_types::Any {
+                type_url: BAD_REQUEST_TYPE_URL.to_string(),
+                value: bad_request.encode_to_vec(),
+            }],
+        }
+        .encode_to_vec();
+
+        Err(Status::with_details(
+            Code::InvalidArgument,
+            message,
+            details.into(),
+        ))
+    }
+}
+
+pub fn validate_payment(request: &PaymentRequest) -> Result<(), Status> {
+    let mut violations = Violations::default();
+
+    if request.user_id.trim().is_empty() {
+        violations.add("user_id", "must not be empty");
+    }
+    match &request.amount {
+        None => violations.add("amount", "is required"),
+        Some(amount) => {
+            if money::exponent(&amount.currency_code).is_none() {
+                violations.add("amount.currency_code", "must be a supported ISO-4217 code");
+            }
+            if amount.minor_units <= 0 {
+                violations.add("amount.minor_units", "must be greater than zero");
+            }
+        }
+    }
+    if request.idempotency_key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        violations.add(
+            "idempotency_key",
+            format!("must be at most {} bytes", MAX_IDEMPOTENCY_KEY_LEN),
+        );
+    }
+
+    violations.into_result()
+}
+
+/// Checks a history request and turns it into a ledger query.
+pub fn history_query(request: &TransactionRequest) -> Result<HistoryQuery, Status> {
+    let mut violations = Violations::default();
+
+    if request.user_id.trim().is_empty() {
+        violations.add("user_id", "must not be empty");
+    }
+
+    let mut millis = |field: &str, time: &Option<Timestamp>| match time {
+        Some(time) if !timestamp::is_valid(time) => {
+            violations.add(
+                field,
+                "must be between 0001-01-01 and 9999-12-31 with nanos in [0, 1e9)",
+            );
+            None
+        }
+        time => time.as_ref().map(timestamp::to_millis),
+    };
+    let start_millis = millis("start_time", &request.start_time);
+    let end_millis = millis("end_time", &request.end_time);
+    if let (Some(start), Some(end)) = (start_millis, end_millis)
+        && start >= end
+    {
+        violations.add("end_time", "must be after start_time");
+    }
+
+    let status = match PaymentStatus::from_i32(request.status) {
+        Some(PaymentStatus::Unspecified) => None,
+        Some(status) => Some(status),
+        None => {
+            violations.add("status", "is not a known PaymentStatus");
+            None
+        }
+    };
+
+    for (field, bound) in [
+        ("min_amount", &request.min_amount),
+        ("max_amount", &request.max_amount),
+    ] {
+        if let Some(bound) = bound
+            && money::exponent(&bound.currency_code).is_none()
+        {
+            violations.add(
+                &format!("{}.currency_code", field),
+                "must be a supported ISO-4217 code",
+            );
+        }
+    }
+    if let (Some(min), Some(max)) = (&request.min_amount, &request.max_amount) {
+        if min.currency_code != max.currency_code {
+            violations.add(
+                "max_amount.currency_code",
+                "must match min_amount.currency_code",
+            );
+        } else if min.minor_units > max.minor_units {
+            violations.add("max_amount", "must not be less than min_amount");
+        }
+    }
+
+    if request.page_size > MAX_PAGE_SIZE {
+        violations.add("page_size", format!("must be at most {}", MAX_PAGE_SIZE));
+    }
+    // A page would end the history early, and the entries after it would then be skipped
+    // by the switch to live updates.
+    if request.follow && request.page_size > 0 {
+        violations.add("page_size", "must be 0 when follow is set");
+    }
+
+    let after_sequence = if request.cursor.is_empty() {
+        0
+    } else {
+        request.cursor.parse().unwrap_or_else(|_| {
+            violations.add("cursor", "is not a cursor returned by this service");
+            0
+        })
+    };
+
+    violations.into_result()?;
+
+    Ok(HistoryQuery {
+        user_id: request.user_id.clone(),
+        after_sequence,
+        start_millis,
+        end_millis,
+        status,
+        min_amount: request.min_amount.clone(),
+        max_amount: request.max_amount.clone(),
+        limit: (request.page_size > 0).then_some(request.page_size as usize),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use grpc_tutorial::services::Money;
+
+    fn violations(status: &Status) -> Vec<(String, String)> {
+        let details = rpc::Status::decode(status.details()).unwrap();
+        assert_eq!(details.code, Code::InvalidArgument as i32);
+        assert_eq!(details.details.len(), 1);
+        assert_eq!(details.details[0].type_url, BAD_REQUEST_TYPE_URL);
+        BadRequest::decode(details.details[0].value.as_slice())
+            .unwrap()
+            .field_violations
+            .into_iter()
+            .map(|v| (v.field, v.description))
+            .collect()
+    }
+
+    fn fields(status: &Status) -> Vec<String> {
+        violations(status)
+            .into_iter()
+            .map(|(field, _)| field)
+            .collect()
+    }
+
+    #[test]
+    fn valid_payment_passes() {
+        let request = PaymentRequest {
+            user_id: "user_123".to_string(),
+            idempotency_key: "key".to_string(),
+            amount: Some(Money::parse("1.00", "USD").unwrap()),
+        };
+        assert!(validate_payment(&request).is_ok());
+    }
+
+    #[test]
+    fn invalid_payment_reports_every_field_as_bad_request_details() {
+        let request = PaymentRequest {
+            user_id: " ".to_string(),
+            idempotency_key: "k".repeat(MAX_IDEMPOTENCY_KEY_LEN + 1),
+            amount: Some(Money {
+                currency_code: "XXX".to_string(),
+                minor_units: 0,
+            }),
+        };
+        let status = validate_payment(&request).unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(
+            fields(&status),
+            [
+                "user_id",
+                "amount.currency_code",
+                "amount.minor_units",
+                "idempotency_key"
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_amount_is_required() {
+        let request = PaymentRequest {
+            user_id: "user_123".to_string(),
+            ..Default::default()
+        };
+        let status = validate_payment(&request).unwrap_err();
+        assert_eq!(
+            violations(&status),
+            [("amount".to_string(), "is required".to_string())]
+        );
+    }
+
+    fn history_request() -> TransactionRequest {
+        TransactionRequest {
+            user_id: "user_123".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn valid_history_request_becomes_a_query() {
+        let request = TransactionRequest {
+            start_time: Some(timestamp::from_millis(1_000)),
+            end_time: Some(timestamp::from_millis(2_000)),
+            min_amount: Some(Money::parse("1", "USD").unwrap()),
+            max_amount: Some(Money::parse("1", "USD").unwrap()),
+            page_size: 10,
+            cursor: "7".to_string(),
+            ..history_request()
+        };
+        let query = history_query(&request).unwrap();
+        assert_eq!(query.start_millis, Some(1_000));
+        assert_eq!(query.end_millis, Some(2_000));
+        assert_eq!(query.after_sequence, 7);
+        assert_eq!(query.limit, Some(10));
+    }
+
+    #[test]
+    fn history_time_range_must_be_in_order() {
+        let request = TransactionRequest {
+            start_time: Some(timestamp::from_millis(2_000)),
+            end_time: Some(timestamp::from_millis(2_000)),
+            ..history_request()
+        };
+        let status = history_query(&request).unwrap_err();
+        assert_eq!(fields(&status), ["end_time"]);
+    }
+
+    #[test]
+    fn history_timestamps_out_of_range_are_rejected() {
+        let request = TransactionRequest {
+            start_time: Some(Timestamp {
+                seconds: i64::MIN,
+                nanos: 0,
+            }),
+            end_time: Some(Timestamp {
+                seconds: 0,
+                nanos: 1_000_000_000,
+            }),
+            ..history_request()
+        };
+        let status = history_query(&request).unwrap_err();
+        assert_eq!(fields(&status), ["start_time", "end_time"]);
+
+        let request = TransactionRequest {
+            end_time: Some(Timestamp {
+                seconds: i64::MAX,
+                nanos: -1,
+            }),
+            ..history_request()
+        };
+        let status = history_query(&request).unwrap_err();
+        assert_eq!(fields(&status), ["end_time"]);
+    }
+
+    #[test]
+    fn history_amount_bounds_must_share_a_currency_and_be_in_order() {
+        let request = TransactionRequest {
+            min_amount: Some(Money::parse("1", "USD").unwrap()),
+            max_amount: Some(Money::parse("1", "EUR").unwrap()),
+            ..history_request()
+        };
+        let status = history_query(&request).unwrap_err();
+        assert_eq!(fields(&status), ["max_amount.currency_code"]);
+
+        let request = TransactionRequest {
+            min_amount: Some(Money::parse("2", "USD").unwrap()),
+            max_amount: Some(Money::parse("1", "USD").unwrap()),
+            ..history_request()
+        };
+        let status = history_query(&request).unwrap_err();
+        assert_eq!(fields(&status), ["max_amount"]);
+
+        let request = TransactionRequest {
+            min_amount: Some(Money {
+                currency_code: "XXX".to_string(),
+                minor_units: 1,
+            }),
+            ..history_request()
+        };
+        let status = history_query(&request).unwrap_err();
+        assert_eq!(fields(&status), ["min_amount.currency_code"]);
+    }
+
+    #[test]
+    fn history_cursor_must_come_from_the_service() {
+        let request = TransactionRequest {
+            cursor: "not-a-cursor".to_string(),
+            page_size: MAX_PAGE_SIZE + 1,
+            ..history_request()
+        };
+        let status = history_query(&request).unwrap_err();
+        assert_eq!(fields(&status), ["page_size", "cursor"]);
+    }
+
+    #[test]
+    fn following_history_cannot_be_paged() {
+        let request = TransactionRequest {
+            page_size: 10,
+            follow: true,
+            ..history_request()
+        };
+        let status = history_query(&request).unwrap_err();
+        assert_eq!(fields(&status), ["page_size"]);
+
+        let request = TransactionRequest {
+            follow: true,
+            ..history_request()
+        };
+        assert_eq!(history_query(&request).unwrap().limit, None);
+    }
+}